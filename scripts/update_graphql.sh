@@ -2,3 +2,4 @@
 
 graphql-client introspect-schema https://gitlab.com/api/graphql > src/providers/graphql/gitlab/schema.json
 wget https://docs.github.com/public/schema.docs.graphql -O src/providers/graphql/github/schema.graphql
+# src/providers/graphql/srht/schema.graphql is a hand-maintained subset of the upstream git.sr.ht schema
@@ -1,19 +1,51 @@
-use super::lock;
+use super::{lock, LockOptions};
+use crate::config::ProviderFilter;
 use crate::lockfile::Lockfile;
 use crate::utils;
 use anyhow::Context;
 use console::style;
+use std::collections::HashSet;
 use std::path::{Path, PathBuf};
 
 use super::get_all_repositories_to_archive;
 
-pub fn archive(workspace: &Path, force: bool) -> anyhow::Result<()> {
+pub fn archive(workspace: &Path, force: bool, providers: &ProviderFilter) -> anyhow::Result<()> {
+    let lockfile = Lockfile::new(workspace.join("workspace-lock.toml"));
+    // When only some providers are selected, only their repositories can be archived. Other
+    // directories aren't in the lockfile either, but we can't tell who they belong to.
+    let selected_paths: Option<HashSet<PathBuf>> = if providers.is_all() {
+        None
+    } else if lockfile.exists() {
+        let previous = lockfile.read().context("Error reading lockfile")?;
+        Some(
+            previous
+                .iter()
+                .filter(|r| providers.matches_repository(r))
+                .map(|r| PathBuf::from(r.name()))
+                .collect(),
+        )
+    } else {
+        Some(HashSet::new())
+    };
+
     // Archive any repositories that have been deleted from the lockfile.
-    lock(workspace)?;
+    lock(
+        workspace,
+        &LockOptions {
+            providers: providers.clone(),
+            ..Default::default()
+        },
+    )?;
 
-    let lockfile = Lockfile::new(workspace.join("workspace-lock.toml"));
     let repositories = lockfile.read().context("Error reading lockfile")?;
-    let repos_to_archive = get_all_repositories_to_archive(workspace, repositories)?;
+    let mut repos_to_archive = get_all_repositories_to_archive(workspace, repositories)?;
+    if let Some(selected_paths) = selected_paths {
+        repos_to_archive.retain(|(from_path, _)| {
+            from_path
+                .strip_prefix(workspace)
+                .is_ok_and(|path| selected_paths.contains(path))
+        });
+    }
 
     if !force {
         for (from_path, to_path) in &repos_to_archive {
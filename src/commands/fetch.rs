@@ -1,4 +1,4 @@
-use super::execute_cmd;
+use super::run::execute_cmd_on;
 use std::path::Path;
 
 /// Run `git fetch` on all our repositories
@@ -10,11 +10,13 @@ pub fn fetch(workspace: &Path, threads: usize) -> anyhow::Result<()> {
         "--recurse-submodules=on-demand",
         "--progress",
     ];
-    execute_cmd(
+    // Archived repositories are read-only, so there's nothing new to fetch
+    execute_cmd_on(
         workspace,
         threads,
         "git".to_string(),
         cmd.iter().map(|s| (*s).to_string()).collect(),
+        |r| !r.is_archived(),
     )?;
     Ok(())
 }
@@ -1,47 +1,373 @@
-use crate::config::Config;
+use crate::config::{check_unique_ids, Config, ProviderFilter, ProviderSource};
 use crate::lockfile::Lockfile;
+use crate::providers::{FetchContext, RateLimits, ResponseCache};
 use crate::repository::Repository;
-use anyhow::Context;
+use crate::utils::error_messages;
+use anyhow::{bail, Context};
+use console::style;
 use indicatif::ParallelProgressIterator;
 use indicatif::{ProgressBar, ProgressStyle};
 use rayon::prelude::*;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::path::Path;
 
-/// Update our lockfile
-pub fn lock(workspace: &Path) -> anyhow::Result<()> {
+/// How to update the lockfile
+#[derive(Default)]
+pub struct LockOptions {
+    /// Read provider responses from the cache, without making any API requests
+    pub offline: bool,
+    /// Keep the previous repositories of providers that fail, instead of failing the whole lock
+    pub keep_going: bool,
+    /// Only fetch these providers. The others keep their repositories from the previous lockfile.
+    pub providers: ProviderFilter,
+}
+
+/// Update our lockfile. Returns the number of providers that failed, which is always zero
+/// without `keep_going`.
+pub fn lock(workspace: &Path, options: &LockOptions) -> anyhow::Result<usize> {
     let config = Config::from_workspace(workspace)?;
 
     // Read the configuration sources
     let sources = config
         .read()
         .with_context(|| "Error reading config files")?;
+    check_unique_ids(&sources)?;
+    let sources: Vec<ProviderSource> = sources
+        .into_iter()
+        .filter(|source| options.providers.is_match(&source.id()))
+        .collect();
+    if sources.is_empty() && !options.providers.is_all() {
+        bail!("No configured providers match the given --provider patterns");
+    }
+    let settings = config
+        .read_settings()
+        .with_context(|| "Error reading config files")?;
+    let path_case = settings.path_case.unwrap_or_default();
 
     let total_bar = ProgressBar::new(sources.len() as u64);
     total_bar.set_style(
         ProgressStyle::default_bar()
-            .template("[{elapsed_precise}] {percent}% [{wide_bar:.cyan/blue}] {pos}/{len} (ETA: {eta_precise})").expect("Invalid template")
+            .template("[{elapsed_precise}] {percent}% [{wide_bar:.cyan/blue}] {pos}/{len} (ETA: {eta_precise}) {msg}").expect("Invalid template")
             .progress_chars("#>-"),
     );
+    // Show how much of each API's rate limit is left next to the progress bar
+    let context = FetchContext {
+        http: settings.http.unwrap_or_default(),
+        rate_limits: RateLimits::with_progress_bar(total_bar.clone()),
+        cache: Some(ResponseCache::new(workspace, options.offline)),
+    };
 
-    println!("Fetching repositories...");
+    if options.offline {
+        println!("Reading repositories from the cache...");
+    } else {
+        println!("Fetching repositories...");
+    }
 
     // For each source, in sequence, fetch the repositories
-    let results = sources
+    let results: Vec<(&ProviderSource, anyhow::Result<Vec<Repository>>)> = sources
         .par_iter()
         .map(|source| {
-            source
-                .fetch_repositories()
-                .with_context(|| format!("Error fetching repositories from {}", source))
+            let result = source
+                .fetch_repositories(&context)
+                .with_context(|| format!("Error fetching repositories from {}", source));
+            (source, result)
         })
         .progress_with(total_bar)
-        .collect::<anyhow::Result<Vec<_>>>()?;
-    let mut all_repositories: Vec<Repository> = results.into_iter().flatten().collect();
-    // let all_repositories: Vec<Repository> = all_repository_results.iter().collect::<anyhow::Result<Vec<Repository>>>()?;
-    // We may have duplicated repositories here. Make sure they are unique based on the full path.
+        .collect();
+    let mut all_repositories: Vec<Repository> = vec![];
+    let mut failed = vec![];
+    for (source, result) in results {
+        match result {
+            Ok(repositories) => all_repositories.extend(
+                repositories
+                    .into_iter()
+                    .map(|r| r.with_path_case(source.path_case(path_case))),
+            ),
+            Err(error) if options.keep_going => failed.push((source, error)),
+            Err(error) => return Err(error),
+        }
+    }
+
+    let lockfile = Lockfile::new(workspace.join("workspace-lock.toml"));
+    let previous = if lockfile.exists() {
+        lockfile.read().context("Error reading lockfile")?
+    } else {
+        vec![]
+    };
+    if !failed.is_empty() || !options.providers.is_all() {
+        let failed_ids: BTreeSet<String> = failed.iter().map(|(source, _)| source.id()).collect();
+        all_repositories.extend(keep_previous_repositories(
+            previous.clone(),
+            &all_repositories,
+            |id| failed_ids.contains(id) || !options.providers.is_match(id),
+        ));
+    }
+    if !failed.is_empty() {
+        eprintln!(
+            "{} providers failed, keeping their repositories from the previous lockfile:",
+            failed.len()
+        );
+        for (source, error) in &failed {
+            eprintln!("{}:", source);
+            error_messages(error)
+                .iter()
+                .skip(1)
+                .for_each(|cause| eprintln!("because: {}", cause));
+        }
+    }
+
+    // We may have duplicated repositories here, when two providers return the same repository.
+    // Make sure they are unique based on the full path and URL.
     all_repositories.sort();
-    all_repositories.dedup();
+    all_repositories.dedup_by(|a, b| a.name() == b.name() && a.url() == b.url());
+    let all_repositories = detect_renames(workspace, &previous, all_repositories);
+    check_for_collisions(&all_repositories)?;
+    let case_collisions = case_collisions(&all_repositories);
+    if !case_collisions.is_empty() {
+        eprintln!(
+            "{} some repositories have paths that only differ by case, and would be cloned into \
+             the same directory on case-insensitive filesystems. Exclude one of them or use a \
+             path template:",
+            style("Warning:").yellow()
+        );
+        for collision in case_collisions {
+            eprintln!("{}", collision);
+        }
+    }
     // Write the lockfile out
-    let lockfile = Lockfile::new(workspace.join("workspace-lock.toml"));
     lockfile.write(&all_repositories)?;
+    Ok(failed.len())
+}
+
+/// The repositories from the previous lockfile that belong to providers that weren't fetched,
+/// because they failed or weren't selected. Entries written before providers were recorded can't
+/// be attributed to one, so they're kept unless a provider that was fetched returned the same
+/// path.
+fn keep_previous_repositories<F>(
+    previous: Vec<Repository>,
+    fetched: &[Repository],
+    not_fetched: F,
+) -> Vec<Repository>
+where
+    F: Fn(&str) -> bool,
+{
+    let fetched_paths: BTreeSet<&String> = fetched.iter().map(Repository::name).collect();
+    previous
+        .into_iter()
+        .filter(|r| match r.provider() {
+            Some(provider) => not_fetched(provider),
+            None => !fetched_paths.contains(r.name()),
+        })
+        .collect()
+}
+
+/// Find repositories that moved to a new path since the previous lockfile, because they were
+/// renamed or transferred, using the provider's id for them. `update` moves their checkouts
+/// instead of cloning them again. A rename stays pending until the old checkout has been moved,
+/// even if the lockfile is written again in the meantime.
+fn detect_renames(
+    workspace: &Path,
+    previous: &[Repository],
+    repositories: Vec<Repository>,
+) -> Vec<Repository> {
+    let previous_by_id: HashMap<(&str, &str), &Repository> = previous
+        .iter()
+        .filter_map(|r| Some(((r.provider()?, r.id()?), r)))
+        .collect();
+    repositories
+        .into_iter()
+        .map(|repository| {
+            let previous = repository
+                .provider()
+                .zip(repository.id())
+                .and_then(|key| previous_by_id.get(&key));
+            let renamed_from = previous.and_then(|previous| {
+                if repository.exists(workspace) {
+                    return None;
+                }
+                [Some(previous.name()), previous.renamed_from()]
+                    .into_iter()
+                    .flatten()
+                    .find(|path| {
+                        *path != repository.name() && workspace.join(path).join(".git").is_dir()
+                    })
+                    .cloned()
+            });
+            repository.with_renamed_from(renamed_from)
+        })
+        .collect()
+}
+
+/// Two different repositories can't be cloned into the same directory, which can happen when a
+/// provider is configured to shorten paths.
+fn check_for_collisions(repositories: &[Repository]) -> anyhow::Result<()> {
+    let mut urls_by_path: BTreeMap<&String, Vec<&String>> = BTreeMap::new();
+    for repository in repositories {
+        let urls = urls_by_path.entry(repository.name()).or_default();
+        if !urls.contains(&repository.url()) {
+            urls.push(repository.url());
+        }
+    }
+
+    let collisions: Vec<String> = urls_by_path
+        .into_iter()
+        .filter(|(_, urls)| urls.len() > 1)
+        .map(|(path, urls)| {
+            let urls: Vec<&str> = urls.into_iter().map(String::as_str).collect();
+            format!("{}: {}", path, urls.join(", "))
+        })
+        .collect();
+    if !collisions.is_empty() {
+        bail!(
+            "Multiple repositories would be cloned into the same directory. Change the path or \
+             layout of the providers so that every repository has a unique path:\n{}",
+            collisions.join("\n")
+        );
+    }
     Ok(())
 }
+
+/// Paths that only differ by case clobber each other on case-insensitive filesystems, like the
+/// defaults on macOS and Windows. They're fine elsewhere, so these are only warned about.
+fn case_collisions(repositories: &[Repository]) -> Vec<String> {
+    let mut paths_by_lowercase_path: BTreeMap<String, Vec<&String>> = BTreeMap::new();
+    for repository in repositories {
+        let paths = paths_by_lowercase_path
+            .entry(repository.name().to_lowercase())
+            .or_default();
+        if !paths.contains(&repository.name()) {
+            paths.push(repository.name());
+        }
+    }
+
+    paths_by_lowercase_path
+        .into_values()
+        .filter(|paths| paths.len() > 1)
+        .map(|paths| {
+            let paths: Vec<&str> = paths.into_iter().map(String::as_str).collect();
+            paths.join(", ")
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::PathCase;
+
+    fn repository(path: &str, url: &str) -> Repository {
+        Repository::new(path.to_string(), url.to_string(), None, None)
+    }
+
+    #[test]
+    fn test_keep_previous_repositories() {
+        let previous = vec![
+            repository("github/org/a", "git@github.com:org/a.git")
+                .with_provider("github:github.com/org".to_string()),
+            repository("gitlab/team/b", "git@gitlab.internal:team/b.git")
+                .with_provider("gitlab:gitlab.internal/team".to_string()),
+            repository(
+                "gitlab/team/deleted",
+                "git@gitlab.internal:team/deleted.git",
+            )
+            .with_provider("gitlab:gitlab.internal/team".to_string()),
+            repository("github/org/old", "git@github.com:org/old.git"),
+            repository("github/org/c", "git@github.com:org/c.git"),
+        ];
+        let fetched = vec![repository("github/org/c", "git@github.com:org/c.git")
+            .with_provider("github:github.com/org".to_string())];
+        let failed_ids = BTreeSet::from(["gitlab:gitlab.internal/team".to_string()]);
+
+        let kept = keep_previous_repositories(previous, &fetched, |id| failed_ids.contains(id));
+        let kept: Vec<&str> = kept.iter().map(|r| r.name().as_str()).collect();
+        assert_eq!(
+            kept,
+            vec!["gitlab/team/b", "gitlab/team/deleted", "github/org/old"]
+        );
+    }
+
+    #[test]
+    fn test_detect_renames() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let workspace = temp_dir.path();
+        std::fs::create_dir_all(workspace.join("github/org/old/.git")).unwrap();
+        std::fs::create_dir_all(workspace.join("github/org/same/.git")).unwrap();
+
+        let github = |path: &str, id: &str| {
+            repository(path, &format!("git@github.com:{}.git", path))
+                .with_provider("github:github.com/org".to_string())
+                .with_id(id.to_string())
+        };
+        let previous = vec![
+            github("github/org/old", "1"),
+            github("github/org/same", "2"),
+            github("github/org/never-cloned", "3"),
+        ];
+        let renamed = detect_renames(
+            workspace,
+            &previous,
+            vec![
+                github("github/org/new", "1"),
+                github("github/org/same", "2"),
+                github("github/org/still-not-cloned", "3"),
+                github("github/org/added", "4"),
+            ],
+        );
+        let renamed_from: Vec<Option<&str>> = renamed
+            .iter()
+            .map(|r| r.renamed_from().map(String::as_str))
+            .collect();
+        assert_eq!(renamed_from, vec![Some("github/org/old"), None, None, None]);
+
+        // The rename is kept until the checkout is moved, even through another rename
+        let renamed = detect_renames(workspace, &renamed, vec![github("github/org/newer", "1")]);
+        assert_eq!(
+            renamed[0].renamed_from().map(String::as_str),
+            Some("github/org/old")
+        );
+
+        std::fs::rename(
+            workspace.join("github/org/old"),
+            workspace.join("github/org/newer"),
+        )
+        .unwrap();
+        let renamed = detect_renames(workspace, &renamed, vec![github("github/org/newer", "1")]);
+        assert_eq!(renamed[0].renamed_from(), None);
+    }
+
+    #[test]
+    fn test_check_for_collisions() {
+        let repositories = vec![
+            repository("gitlab/team/service", "git@gitlab.com:a/team/service.git"),
+            repository("gitlab/team/other", "git@gitlab.com:a/team/other.git"),
+        ];
+        assert!(check_for_collisions(&repositories).is_ok());
+
+        let repositories = vec![
+            repository("gitlab/team/service", "git@gitlab.com:a/team/service.git"),
+            repository("gitlab/team/service", "git@gitlab.com:b/team/service.git"),
+        ];
+        let error = check_for_collisions(&repositories).unwrap_err().to_string();
+        assert!(error.contains(
+            "gitlab/team/service: git@gitlab.com:a/team/service.git, git@gitlab.com:b/team/service.git"
+        ));
+
+        let repositories = vec![
+            repository("github/org/Foo", "git@github.com:org/Foo.git"),
+            repository("github/org/foo", "git@github.com:org/foo.git"),
+        ];
+        assert!(check_for_collisions(&repositories).is_ok());
+        assert_eq!(
+            case_collisions(&repositories),
+            vec!["github/org/Foo, github/org/foo".to_string()]
+        );
+
+        // Lowercasing the paths turns this into a plain collision
+        let repositories: Vec<Repository> = repositories
+            .into_iter()
+            .map(|r| r.with_path_case(PathCase::Lower))
+            .collect();
+        let error = check_for_collisions(&repositories).unwrap_err().to_string();
+        assert!(error.contains("github/org/foo: git@github.com:org/Foo.git"));
+    }
+}
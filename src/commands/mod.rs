@@ -13,7 +13,7 @@ pub use archive::archive;
 pub use completion::completion;
 pub use fetch::fetch;
 pub use list::list;
-pub use lock::lock;
+pub use lock::{lock, LockOptions};
 pub use run::execute_cmd;
 pub use switch_and_pull::pull_all_repositories;
 pub use update::update;
@@ -137,6 +137,16 @@ pub fn get_all_repositories_to_archive(
         .filter_map(Result::ok)
         .collect();
 
+    // The checkouts of renamed repositories are moved by `update`, so they aren't archived
+    // while the move is pending.
+    repository_paths.extend(
+        repositories
+            .iter()
+            .filter(|r| !r.exists(workspace))
+            .filter_map(|r| r.renamed_from())
+            .filter_map(|path| workspace.join(path).canonicalize().ok()),
+    );
+
     // If the archive directory does not exist then we create it
     if !archive_directory.exists() {
         fs_extra::dir::create(&archive_directory, false).with_context(|| {
@@ -155,6 +165,16 @@ pub fn get_all_repositories_to_archive(
             .with_context(|| "Error canoncalizing archive directory")?,
     );
 
+    // The response cache doesn't contain any repositories either
+    let cache_directory = workspace.join(".git-workspace");
+    if cache_directory.exists() {
+        repository_paths.insert(
+            cache_directory
+                .canonicalize()
+                .with_context(|| "Error canoncalizing cache directory")?,
+        );
+    }
+
     let mut to_archive = Vec::new();
     let mut it = WalkDir::new(workspace).into_iter();
 
@@ -10,6 +10,20 @@ pub fn execute_cmd(
     cmd: String,
     args: Vec<String>,
 ) -> anyhow::Result<()> {
+    execute_cmd_on(workspace, threads, cmd, args, |_| true)
+}
+
+/// Execute a command on the repositories that `filter` returns true for
+pub(crate) fn execute_cmd_on<F>(
+    workspace: &Path,
+    threads: usize,
+    cmd: String,
+    args: Vec<String>,
+    filter: F,
+) -> anyhow::Result<()>
+where
+    F: Fn(&Repository) -> bool,
+{
     // Read the lockfile
     let lockfile = Lockfile::new(workspace.join("workspace-lock.toml"));
     let repositories = lockfile.read()?;
@@ -18,6 +32,7 @@ pub fn execute_cmd(
     let repos_to_fetch: Vec<Repository> = repositories
         .iter()
         .filter(|r| r.exists(workspace))
+        .filter(|r| filter(r))
         .cloned()
         .collect();
 
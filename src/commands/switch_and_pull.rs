@@ -5,7 +5,13 @@ use std::path::Path;
 
 pub fn pull_all_repositories(workspace: &Path, threads: usize) -> anyhow::Result<()> {
     let lockfile = Lockfile::new(workspace.join("workspace-lock.toml"));
-    let repositories = lockfile.read().with_context(|| "Error reading lockfile")?;
+    let repositories: Vec<_> = lockfile
+        .read()
+        .with_context(|| "Error reading lockfile")?
+        .into_iter()
+        // Archived repositories are read-only, so there's nothing new to pull
+        .filter(|r| !r.is_archived())
+        .collect();
 
     println!(
         "Switching to the primary branch and pulling {} repositories",
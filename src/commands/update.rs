@@ -1,24 +1,79 @@
 use super::map_repositories;
 use crate::commands::get_all_repositories_to_archive;
+use crate::config::{Config, ProviderFilter};
 use crate::lockfile::Lockfile;
+use crate::providers::HttpOptions;
+use crate::repository::Repository;
 use anyhow::Context;
 use console::style;
+use std::collections::HashMap;
 use std::path::Path;
 
-/// Update our workspace. This clones any new repositories and print old repositories to archives.
-pub fn update(workspace: &Path, threads: usize) -> anyhow::Result<()> {
+/// The HTTP options used to download `commit-msg` hooks: the workspace's `[http]` section, and
+/// each provider's options merged with it by provider id
+fn hook_http_options(
+    workspace: &Path,
+) -> anyhow::Result<(HttpOptions, HashMap<String, HttpOptions>)> {
+    let config = Config::from_workspace(workspace)?;
+    let workspace_options = config
+        .read_settings()
+        .with_context(|| "Error reading config files")?
+        .http
+        .unwrap_or_default();
+    let provider_options = config
+        .read()
+        .with_context(|| "Error reading config files")?
+        .iter()
+        .filter_map(|source| Some((source.id(), source.http()?.merge(&workspace_options))))
+        .collect();
+    Ok((workspace_options, provider_options))
+}
+
+/// Update our workspace. This clones any new repositories of the selected providers and print
+/// old repositories to archives.
+pub fn update(workspace: &Path, threads: usize, providers: &ProviderFilter) -> anyhow::Result<()> {
     // Load our lockfile
     let lockfile = Lockfile::new(workspace.join("workspace-lock.toml"));
     let repositories = lockfile.read().with_context(|| "Error reading lockfile")?;
+    let selected: Vec<Repository> = repositories
+        .iter()
+        .filter(|r| providers.matches_repository(r))
+        .cloned()
+        .collect();
 
-    println!("Updating {} repositories", repositories.len());
+    // Move the checkouts of renamed repositories, rather than cloning them again
+    for repository in &selected {
+        if repository.move_renamed(workspace)? {
+            repository.set_upstream(workspace)?;
+            println!(
+                "Moved {} to {}",
+                style(repository.renamed_from().unwrap()).yellow(),
+                style(repository.name()).green()
+            );
+        }
+    }
 
-    map_repositories(&repositories, threads, |r, progress_bar| {
+    // Only read the configuration if it's needed, as the lockfile is enough to clone
+    let (workspace_options, provider_options) = if selected.iter().any(|r| r.has_commit_msg_hook())
+    {
+        hook_http_options(workspace)?
+    } else {
+        Default::default()
+    };
+
+    println!("Updating {} repositories", selected.len());
+
+    map_repositories(&selected, threads, |r, progress_bar| {
         // Only clone repositories that don't exist
         if !r.exists(workspace) {
             r.clone(workspace, progress_bar)?;
             // Maybe this should always be run, but whatever. It's fine for now.
             r.set_upstream(workspace)?;
+            let http = r
+                .provider()
+                .and_then(|id| provider_options.get(id))
+                .unwrap_or(&workspace_options);
+            r.install_commit_msg_hook(workspace, http)?;
         }
         Ok(())
     })?;
@@ -37,3 +92,39 @@ pub fn update(workspace: &Path, threads: usize) -> anyhow::Result<()> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_hook_http_options() {
+        let temp_dir = TempDir::new().unwrap();
+        std::fs::write(
+            temp_dir.path().join("workspace.toml"),
+            r#"[http]
+            proxy = "http://proxy:8080"
+            read_timeout = 30
+
+            [[provider]]
+            provider = "gerrit"
+            url = "https://review.example.com"
+            path = "gerrit"
+            commit_msg_hook = true
+            read_timeout = 60
+
+            [[provider]]
+            provider = "manual"
+            repo = []"#,
+        )
+        .unwrap();
+
+        let (workspace_options, provider_options) = hook_http_options(temp_dir.path()).unwrap();
+        assert_eq!(workspace_options.read_timeout, Some(30));
+        let gerrit = &provider_options["gerrit:review.example.com"];
+        assert_eq!(gerrit.proxy.as_deref(), Some("http://proxy:8080"));
+        assert_eq!(gerrit.read_timeout, Some(60));
+        assert!(!provider_options.contains_key("manual"));
+    }
+}
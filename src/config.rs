@@ -1,21 +1,129 @@
-use crate::providers::{GiteaProvider, GithubProvider, GitlabProvider, Provider};
+use crate::providers::{
+    url_host, AzureDevOpsProvider, BitbucketProvider, BitbucketServerProvider, FetchContext,
+    GerritProvider, GiteaProvider, GithubProvider, GitlabProvider, HttpOptions, ManualProvider,
+    Provider, SrhtProvider,
+};
 use crate::repository::Repository;
 use anyhow::Context;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
 use std::fmt;
 use std::fs;
 use std::path::{Path, PathBuf};
 
 #[derive(Deserialize, Serialize, Debug)]
 struct ConfigContents {
+    #[serde(flatten)]
+    settings: WorkspaceSettings,
     #[serde(rename = "provider", default)]
     providers: Vec<ProviderSource>,
 }
 
+/// How the case of repository paths is treated
+#[derive(Deserialize, Serialize, Default, Debug, Clone, Copy, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum PathCase {
+    /// Keep paths as the provider returns them
+    #[default]
+    Preserve,
+    /// Lowercase every path
+    Lower,
+}
+
+impl PathCase {
+    pub fn apply(&self, path: &str) -> String {
+        match self {
+            PathCase::Preserve => path.to_string(),
+            PathCase::Lower => path.to_lowercase(),
+        }
+    }
+}
+
+/// Settings that apply to the whole workspace rather than a single provider. These can be set
+/// in any of the configuration files, and the first file to set one wins.
+#[derive(Deserialize, Serialize, Default, Debug, Clone, Eq, PartialEq)]
+pub struct WorkspaceSettings {
+    #[serde(default)]
+    pub path_case: Option<PathCase>,
+    #[serde(default)]
+    pub http: Option<HttpOptions>,
+}
+
+impl WorkspaceSettings {
+    fn merge(self, other: WorkspaceSettings) -> WorkspaceSettings {
+        WorkspaceSettings {
+            path_case: self.path_case.or(other.path_case),
+            http: match (self.http, other.http) {
+                (Some(http), Some(other_http)) => Some(http.merge(&other_http)),
+                (http, other_http) => http.or(other_http),
+            },
+        }
+    }
+}
+
 pub struct Config {
     files: Vec<PathBuf>,
 }
 
+/// Which providers a command applies to, as glob patterns matched against the provider ids,
+/// e.g. "github:*". Without any patterns every provider matches.
+#[derive(Clone, Debug, Default)]
+pub struct ProviderFilter {
+    patterns: Option<globset::GlobSet>,
+}
+
+impl ProviderFilter {
+    pub fn new(patterns: &[String]) -> anyhow::Result<ProviderFilter> {
+        if patterns.is_empty() {
+            return Ok(ProviderFilter::default());
+        }
+        let mut builder = globset::GlobSetBuilder::new();
+        for pattern in patterns {
+            builder.add(
+                globset::Glob::new(pattern)
+                    .with_context(|| format!("Invalid provider pattern {}", pattern))?,
+            );
+        }
+        Ok(ProviderFilter {
+            patterns: Some(builder.build()?),
+        })
+    }
+
+    pub fn is_all(&self) -> bool {
+        self.patterns.is_none()
+    }
+
+    pub fn is_match(&self, id: &str) -> bool {
+        self.patterns
+            .as_ref()
+            .map_or(true, |patterns| patterns.is_match(id))
+    }
+
+    /// Repositories from lockfiles written before provider ids were recorded only match when
+    /// every provider does.
+    pub fn matches_repository(&self, repository: &Repository) -> bool {
+        self.is_all() || repository.provider().is_some_and(|id| self.is_match(id))
+    }
+}
+
+/// Make sure every provider has its own id, so the repositories in the lockfile can be traced
+/// back to the provider they came from. Manual providers never fail to fetch and can't be told
+/// apart, so they all share the `manual` id.
+pub fn check_unique_ids(sources: &[ProviderSource]) -> anyhow::Result<()> {
+    let mut ids = BTreeSet::new();
+    for source in sources {
+        let id = source.id();
+        if id != "manual" && !ids.insert(id.clone()) {
+            anyhow::bail!(
+                "Multiple providers have the id {}. Give them different names, filters or \
+                 tokens so their repositories can be told apart.",
+                id
+            );
+        }
+    }
+    Ok(())
+}
+
 impl Config {
     pub fn new(files: Vec<PathBuf>) -> Config {
         Config { files }
@@ -52,6 +160,13 @@ impl Config {
         Ok(Self::new(config_files))
     }
 
+    fn read_file(path: &Path) -> anyhow::Result<ConfigContents> {
+        let file_contents = fs::read_to_string(path)
+            .with_context(|| format!("Cannot read file {}", path.display()))?;
+        toml::from_str(file_contents.as_str())
+            .with_context(|| format!("Error parsing TOML in file {}", path.display()))
+    }
+
     pub fn read(&self) -> anyhow::Result<Vec<ProviderSource>> {
         let mut all_providers = vec![];
 
@@ -59,16 +174,34 @@ impl Config {
             if !path.exists() {
                 continue;
             }
-            let file_contents = fs::read_to_string(path)
-                .with_context(|| format!("Cannot read file {}", path.display()))?;
-            let contents: ConfigContents = toml::from_str(file_contents.as_str())
-                .with_context(|| format!("Error parsing TOML in file {}", path.display()))?;
-            all_providers.extend(contents.providers);
+            all_providers.extend(Self::read_file(path)?.providers);
         }
         Ok(all_providers)
     }
+
+    pub fn read_settings(&self) -> anyhow::Result<WorkspaceSettings> {
+        let mut settings = WorkspaceSettings::default();
+
+        for path in &self.files {
+            if !path.exists() {
+                continue;
+            }
+            settings = settings.merge(Self::read_file(path)?.settings);
+        }
+        Ok(settings)
+    }
+
+    /// Write the providers to the given file, keeping any workspace settings already in it
     pub fn write(&self, providers: Vec<ProviderSource>, config_path: &Path) -> anyhow::Result<()> {
-        let toml = toml::to_string(&ConfigContents { providers })?;
+        let settings = if config_path.exists() {
+            Self::read_file(config_path)?.settings
+        } else {
+            WorkspaceSettings::default()
+        };
+        let toml = toml::to_string(&ConfigContents {
+            settings,
+            providers,
+        })?;
         fs::write(config_path, toml)
             .with_context(|| format!("Error writing to file {}", config_path.display()))?;
         Ok(())
@@ -80,17 +213,32 @@ impl Config {
 #[serde(rename_all = "lowercase")]
 #[derive(clap::Subcommand)]
 pub enum ProviderSource {
+    #[serde(rename = "azure-devops")]
+    #[command(name = "azure-devops")]
+    AzureDevOps(AzureDevOpsProvider),
+    Bitbucket(BitbucketProvider),
+    #[serde(rename = "bitbucket-server")]
+    BitbucketServer(BitbucketServerProvider),
+    Gerrit(GerritProvider),
     Gitea(GiteaProvider),
     Gitlab(GitlabProvider),
     Github(GithubProvider),
+    Manual(ManualProvider),
+    Srht(SrhtProvider),
 }
 
 impl ProviderSource {
     pub fn provider(&self) -> &dyn Provider {
         match self {
+            Self::AzureDevOps(config) => config,
+            Self::Bitbucket(config) => config,
+            Self::BitbucketServer(config) => config,
+            Self::Gerrit(config) => config,
             Self::Gitea(config) => config,
             Self::Gitlab(config) => config,
             Self::Github(config) => config,
+            Self::Manual(config) => config,
+            Self::Srht(config) => config,
         }
     }
 
@@ -98,8 +246,84 @@ impl ProviderSource {
         self.provider().correctly_configured()
     }
 
-    pub fn fetch_repositories(&self) -> anyhow::Result<Vec<Repository>> {
-        self.provider().fetch_repositories()
+    /// The provider's own HTTP options, which take precedence over the workspace's `[http]`
+    /// section. Manual providers don't talk to an API.
+    pub fn http(&self) -> Option<&HttpOptions> {
+        match self {
+            Self::AzureDevOps(config) => Some(&config.http),
+            Self::Bitbucket(config) => Some(&config.http),
+            Self::BitbucketServer(config) => Some(&config.http),
+            Self::Gerrit(config) => Some(&config.http),
+            Self::Gitea(config) => Some(&config.http),
+            Self::Gitlab(config) => Some(&config.http),
+            Self::Github(config) => Some(&config.http),
+            Self::Manual(_) => None,
+            Self::Srht(config) => Some(&config.http),
+        }
+    }
+
+    /// The path case policy for the provider's repositories. Manual repositories are written
+    /// by hand, so they go into the lockfile as they are.
+    pub fn path_case(&self, path_case: PathCase) -> PathCase {
+        match self {
+            Self::Manual(_) => PathCase::Preserve,
+            _ => path_case,
+        }
+    }
+
+    /// A stable id for the provider, made from its kind, host and user or group name, and the
+    /// configuration that tells providers without a name apart, e.g.
+    /// `gerrit:review.example.com?prefix=platform/`. This is recorded in the lockfile to know
+    /// which provider each repository came from.
+    pub fn id(&self) -> String {
+        let qualifiers = match self {
+            Self::Gerrit(config) => config.id_qualifiers(),
+            Self::Gitea(config) => config.id_qualifiers(),
+            Self::Github(config) => config.id_qualifiers(),
+            _ => vec![],
+        };
+        let id = self.unqualified_id();
+        if qualifiers.is_empty() {
+            return id;
+        }
+        let qualifiers: Vec<String> = qualifiers
+            .into_iter()
+            .map(|(key, value)| format!("{}={}", key, value))
+            .collect();
+        format!("{}?{}", id, qualifiers.join("&"))
+    }
+
+    fn unqualified_id(&self) -> String {
+        let (kind, url, name) = match self {
+            Self::AzureDevOps(config) => ("azure-devops", &config.url, config.name.as_str()),
+            Self::Bitbucket(config) => ("bitbucket", &config.url, config.name.as_str()),
+            Self::BitbucketServer(config) => {
+                ("bitbucket-server", &config.url, config.name.as_str())
+            }
+            Self::Gerrit(config) => ("gerrit", &config.url, ""),
+            Self::Gitea(config) => ("gitea", &config.url, config.owner_name()),
+            Self::Gitlab(config) => ("gitlab", &config.url, config.name.as_str()),
+            Self::Github(config) => ("github", &config.url, config.name.as_str()),
+            // Manually configured repositories never fail to fetch
+            Self::Manual(_) => return "manual".to_string(),
+            Self::Srht(config) => ("srht", &config.url, config.name.as_str()),
+        };
+        let host = url_host(url).trim_start_matches("api.");
+        if name.is_empty() {
+            format!("{}:{}", kind, host)
+        } else {
+            format!("{}:{}/{}", kind, host, name.to_lowercase())
+        }
+    }
+
+    pub fn fetch_repositories(&self, context: &FetchContext) -> anyhow::Result<Vec<Repository>> {
+        let id = self.id();
+        Ok(self
+            .provider()
+            .fetch_repositories(context)?
+            .into_iter()
+            .map(|r| r.with_provider(id.clone()))
+            .collect())
     }
 }
 
@@ -198,6 +422,54 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_config_read_manual_provider() {
+        let temp_dir = TempDir::new().unwrap();
+        let dir_path = temp_dir.path();
+
+        create_test_config(
+            dir_path,
+            "workspace.toml",
+            r#"[[provider]]
+            provider = "manual"
+            [[provider.repo]]
+            path = "kernel/git"
+            url = "https://git.kernel.org/pub/scm/git/git.git"
+            branch = "master"
+            [[provider.repo]]
+            path = "mirrors/cgit"
+            url = "git@mirror.company.com:cgit.git"
+            upstream = "https://git.zx2c4.com/cgit""#,
+        );
+
+        let config = Config::from_workspace(dir_path).unwrap();
+        let providers = config.read().unwrap();
+        assert_eq!(providers.len(), 1);
+
+        let repositories = providers[0]
+            .fetch_repositories(&FetchContext::default())
+            .unwrap();
+        assert_eq!(
+            repositories,
+            vec![
+                Repository::new(
+                    "kernel/git".to_string(),
+                    "https://git.kernel.org/pub/scm/git/git.git".to_string(),
+                    Some("master".to_string()),
+                    None,
+                )
+                .with_provider("manual".to_string()),
+                Repository::new(
+                    "mirrors/cgit".to_string(),
+                    "git@mirror.company.com:cgit.git".to_string(),
+                    None,
+                    Some("https://git.zx2c4.com/cgit".to_string()),
+                )
+                .with_provider("manual".to_string()),
+            ]
+        );
+    }
+
     #[test]
     fn test_config_write() {
         let temp_dir = TempDir::new().unwrap();
@@ -215,6 +487,166 @@ mod tests {
         assert!(content.contains("gitlab"));
     }
 
+    #[test]
+    fn test_provider_filter() {
+        let github = Repository::new("a".to_string(), "b".to_string(), None, None)
+            .with_provider("github:github.com/orf".to_string());
+        let gitlab = Repository::new("a".to_string(), "b".to_string(), None, None)
+            .with_provider("gitlab:gitlab.com/team".to_string());
+        let unknown = Repository::new("a".to_string(), "b".to_string(), None, None);
+
+        let filter = ProviderFilter::default();
+        assert!(filter.is_all());
+        assert!(filter.matches_repository(&github));
+        assert!(filter.matches_repository(&unknown));
+
+        let filter = ProviderFilter::new(&["github:*".to_string()]).unwrap();
+        assert!(!filter.is_all());
+        assert!(filter.is_match("github:github.com/orf"));
+        assert!(filter.matches_repository(&github));
+        assert!(!filter.matches_repository(&gitlab));
+        assert!(!filter.matches_repository(&unknown));
+
+        assert!(ProviderFilter::new(&["github:[".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_provider_id() {
+        let contents: ConfigContents = toml::from_str(
+            r#"[[provider]]
+            provider = "github"
+            name = "Orf"
+            url = "https://api.github.com/graphql"
+            path = "github"
+
+            [[provider]]
+            provider = "gitlab"
+            name = "team"
+            url = "https://gitlab.internal:8443"
+            path = "gitlab"
+
+            [[provider]]
+            provider = "gerrit"
+            url = "https://review.example.com/"
+            path = "gerrit"
+
+            [[provider]]
+            provider = "gerrit"
+            url = "https://review.example.com/"
+            path = "gerrit"
+            prefix = "platform/"
+
+            [[provider]]
+            provider = "gitea"
+            name = "ignored"
+            url = "https://codeberg.org"
+            path = "gitea"
+            kind = "self"
+
+            [[provider]]
+            provider = "gitea"
+            url = "https://codeberg.org"
+            path = "gitea"
+            kind = "self"
+            env_var = "WORK_TOKEN"
+
+            [[provider]]
+            provider = "github"
+            url = "https://api.github.com/graphql"
+            path = "github"
+            viewer = true
+
+            [[provider]]
+            provider = "github"
+            url = "https://api.github.com/graphql"
+            path = "github"
+            viewer = true
+            token_command = "gh auth token"
+            affiliations = ["owner", "organization_member"]
+            owners = ["Orf"]
+
+            [[provider]]
+            provider = "manual"
+            repo = []"#,
+        )
+        .unwrap();
+        let ids: Vec<String> = contents.providers.iter().map(ProviderSource::id).collect();
+        assert_eq!(
+            ids,
+            vec![
+                "github:github.com/orf",
+                "gitlab:gitlab.internal/team",
+                "gerrit:review.example.com",
+                "gerrit:review.example.com?prefix=platform/",
+                "gitea:codeberg.org",
+                "gitea:codeberg.org?token=env:WORK_TOKEN",
+                "github:github.com",
+                "github:github.com?token=command:gh auth token&affiliation=owner,organization-member&owner=orf",
+                "manual",
+            ]
+        );
+        assert!(check_unique_ids(&contents.providers).is_ok());
+
+        let mut providers = contents.providers;
+        providers.push(toml::from_str(r#"provider = "manual""#).unwrap());
+        assert!(check_unique_ids(&providers).is_ok());
+        providers.push(
+            toml::from_str(
+                r#"provider = "gerrit"
+                url = "https://review.example.com"
+                path = "other""#,
+            )
+            .unwrap(),
+        );
+        let error = check_unique_ids(&providers).unwrap_err().to_string();
+        assert!(error.contains("gerrit:review.example.com."), "{}", error);
+    }
+
+    #[test]
+    fn test_config_settings() {
+        let temp_dir = TempDir::new().unwrap();
+        let dir_path = temp_dir.path();
+        create_test_config(dir_path, "workspace.toml", WORKSPACE_FILE_CONTENT);
+        create_test_config(
+            dir_path,
+            "workspace-lower.toml",
+            r#"path_case = "lower"
+
+            [http]
+            proxy = "http://proxy:8080"
+            read_timeout = 30"#,
+        );
+
+        let config = Config::from_workspace(dir_path).unwrap();
+        let settings = config.read_settings().unwrap();
+        assert_eq!(settings.path_case, Some(PathCase::Lower));
+        assert_eq!(
+            ProviderSource::Github(GithubProvider::default()).path_case(PathCase::Lower),
+            PathCase::Lower
+        );
+        assert_eq!(
+            ProviderSource::Manual(ManualProvider::default()).path_case(PathCase::Lower),
+            PathCase::Preserve
+        );
+        let http = settings.http.unwrap();
+        assert_eq!(http.proxy.as_deref(), Some("http://proxy:8080"));
+        assert_eq!(http.read_timeout, Some(30));
+
+        // Rewriting the providers keeps the settings in the file
+        let config_path = dir_path.join("workspace-lower.toml");
+        let config = Config::new(vec![config_path.clone()]);
+        config
+            .write(
+                vec![ProviderSource::Github(GithubProvider::default())],
+                &config_path,
+            )
+            .unwrap();
+        assert_eq!(config.read().unwrap().len(), 1);
+        let settings = config.read_settings().unwrap();
+        assert_eq!(settings.path_case, Some(PathCase::Lower));
+        assert_eq!(settings.http.unwrap().read_timeout, Some(30));
+    }
+
     #[test]
     fn test_invalid_config_content() {
         let temp_dir = TempDir::new().unwrap();
@@ -1,11 +1,13 @@
+use anyhow::bail;
 use clap::{CommandFactory, Parser};
 use git_workspace::commands::{
     add_provider_to_config, archive, completion, execute_cmd, fetch, list, lock,
-    pull_all_repositories, update,
+    pull_all_repositories, update, LockOptions,
 };
-use git_workspace::config::ProviderSource;
-use git_workspace::utils::{ensure_workspace_dir_exists, expand_workspace_path};
+use git_workspace::config::{ProviderFilter, ProviderSource};
+use git_workspace::utils::{ensure_workspace_dir_exists, error_messages, expand_workspace_path};
 use std::path::PathBuf;
+use std::process::ExitCode;
 
 #[derive(clap::Parser)]
 #[command(name = "git-workspace", author, about, version)]
@@ -22,6 +24,10 @@ enum Command {
     Update {
         #[arg(short = 't', long = "threads", default_value = "8")]
         threads: usize,
+        #[command(flatten)]
+        providers: ProviderSelection,
+        #[command(flatten)]
+        failures: ProviderFailures,
     },
     /// Fetch new commits for all repositories in the workspace
     Fetch {
@@ -29,7 +35,15 @@ enum Command {
         threads: usize,
     },
     /// Fetch all repositories from configured providers and write the lockfile
-    Lock {},
+    Lock {
+        /// Use cached provider responses instead of calling the providers' APIs
+        #[arg(long = "offline")]
+        offline: bool,
+        #[command(flatten)]
+        providers: ProviderSelection,
+        #[command(flatten)]
+        failures: ProviderFailures,
+    },
     /// Pull new commits on the primary branch for all repositories in the workspace
     SwitchAndPull {
         #[arg(short = 't', long = "threads", default_value = "8")]
@@ -48,6 +62,8 @@ enum Command {
         /// Disable confirmation prompt
         #[arg(long = "force")]
         force: bool,
+        #[command(flatten)]
+        providers: ProviderSelection,
     },
     /// Run a git command in all repositories
     ///
@@ -66,7 +82,7 @@ enum Command {
         #[arg(long = "file", default_value = "workspace.toml")]
         file: PathBuf,
         #[command(subcommand)]
-        command: ProviderSource,
+        command: Box<ProviderSource>,
     },
     /// Generate shell completions
     Completion {
@@ -75,10 +91,55 @@ enum Command {
     },
 }
 
-fn main() -> anyhow::Result<()> {
+#[derive(clap::Args)]
+struct ProviderSelection {
+    /// Only use providers whose id matches this glob pattern, e.g. "github:*". The ids are
+    /// recorded in the lockfile. Can be repeated.
+    #[arg(long = "provider")]
+    providers: Vec<String>,
+}
+
+impl ProviderSelection {
+    fn filter(&self) -> anyhow::Result<ProviderFilter> {
+        ProviderFilter::new(&self.providers)
+    }
+}
+
+#[derive(clap::Args)]
+struct ProviderFailures {
+    /// Keep the previous repositories of providers that fail, rather than stopping
+    #[arg(long = "keep-going")]
+    keep_going: bool,
+    /// Exit with an error after updating if any provider failed
+    #[arg(long = "exit-code", requires = "keep_going")]
+    exit_code: bool,
+}
+
+impl ProviderFailures {
+    fn check(&self, failed: usize) -> anyhow::Result<()> {
+        if self.exit_code && failed > 0 {
+            bail!("{} providers failed", failed);
+        }
+        Ok(())
+    }
+}
+
+fn main() -> ExitCode {
     // Parse our arguments to Args using clap.
     let args = Args::parse();
-    handle_main(args)
+    match handle_main(args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            // Print the causes like anyhow would, but ureq's errors repeat their cause
+            let messages = error_messages(&error);
+            eprintln!("Error: {}", messages[0]);
+            messages
+                .iter()
+                .skip(1)
+                .for_each(|cause| eprintln!("because: {}", cause));
+            ExitCode::FAILURE
+        }
+    }
 }
 
 fn handle_main(args: Args) -> anyhow::Result<()> {
@@ -88,16 +149,39 @@ fn handle_main(args: Args) -> anyhow::Result<()> {
     // Run our sub command. Pretty self-explanatory.
     match args.command {
         Command::List { full } => list(&workspace_path, full)?,
-        Command::Update { threads } => {
-            lock(&workspace_path)?;
-            update(&workspace_path, threads)?
+        Command::Update {
+            threads,
+            providers,
+            failures,
+        } => {
+            let providers = providers.filter()?;
+            let options = LockOptions {
+                keep_going: failures.keep_going,
+                providers: providers.clone(),
+                ..Default::default()
+            };
+            let failed = lock(&workspace_path, &options)?;
+            update(&workspace_path, threads, &providers)?;
+            failures.check(failed)?
         }
-        Command::Lock {} => {
-            lock(&workspace_path)?;
+        Command::Lock {
+            offline,
+            providers,
+            failures,
+        } => {
+            let options = LockOptions {
+                offline,
+                keep_going: failures.keep_going,
+                providers: providers.filter()?,
+            };
+            let failed = lock(&workspace_path, &options)?;
+            failures.check(failed)?
+        }
+        Command::Archive { force, providers } => {
+            archive(&workspace_path, force, &providers.filter()?)?
         }
-        Command::Archive { force } => archive(&workspace_path, force)?,
         Command::Fetch { threads } => fetch(&workspace_path, threads)?,
-        Command::Add { file, command } => add_provider_to_config(&workspace_path, command, &file)?,
+        Command::Add { file, command } => add_provider_to_config(&workspace_path, *command, &file)?,
         Command::Run {
             threads,
             command,
@@ -108,3 +192,21 @@ fn handle_main(args: Args) -> anyhow::Result<()> {
     };
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_add_azure_devops() {
+        let args =
+            Args::try_parse_from(["git-workspace", "-w", "/tmp", "add", "azure-devops", "org"])
+                .unwrap();
+        let Command::Add { command, .. } = args.command else {
+            panic!("Expected the add command");
+        };
+        // The subcommand is named like the provider in workspace.toml
+        let toml = toml::to_string(&command).unwrap();
+        assert!(toml.contains("provider = \"azure-devops\""), "{}", toml);
+    }
+}
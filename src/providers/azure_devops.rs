@@ -0,0 +1,336 @@
+use crate::providers::{
+    create_exclude_regex_set, create_include_regex_set, url_host, CloneUrlOptions, FetchContext,
+    HttpOptions, PathTemplate, Provider, TokenSource,
+};
+use crate::repository::Repository;
+use anyhow::Context;
+use base64::{engine::general_purpose, Engine};
+use console::style;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+#[derive(Deserialize, Debug)]
+struct AzureDevOpsRepositoryList {
+    value: Vec<AzureDevOpsRepository>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct AzureDevOpsRepository {
+    id: String,
+    name: String,
+    project: AzureDevOpsProject,
+    // Empty repositories do not have a default branch
+    default_branch: Option<String>,
+    remote_url: String,
+    ssh_url: String,
+    #[serde(default)]
+    is_disabled: bool,
+    #[serde(default)]
+    is_fork: bool,
+}
+
+#[derive(Deserialize, Debug)]
+struct AzureDevOpsProject {
+    name: String,
+}
+
+impl AzureDevOpsRepository {
+    fn full_name(&self) -> String {
+        format!("{}/{}", self.project.name, self.name)
+    }
+}
+
+fn default_env_var() -> String {
+    String::from("AZURE_DEVOPS_TOKEN")
+}
+
+static DEFAULT_AZURE_DEVOPS_URL: &str = "https://dev.azure.com";
+
+fn public_azure_devops_url() -> String {
+    DEFAULT_AZURE_DEVOPS_URL.to_string()
+}
+
+#[derive(Deserialize, Serialize, Default, Debug, Eq, Ord, PartialEq, PartialOrd, clap::Parser)]
+#[serde(rename_all = "lowercase")]
+#[command(about = "Add an Azure DevOps organization by name")]
+pub struct AzureDevOpsProvider {
+    /// The name of the Azure DevOps organization to add
+    pub name: String,
+
+    #[arg(long = "project")]
+    #[serde(default)]
+    /// Only clone repositories from these projects. By default all projects in the
+    /// organization are used.
+    projects: Vec<String>,
+
+    #[arg(long = "path", default_value = "azure")]
+    /// Clone repos to a specific path
+    path: String,
+
+    #[arg(long = "env-name", short = 'e', default_value = "AZURE_DEVOPS_TOKEN")]
+    #[serde(default = "default_env_var")]
+    /// Environment variable containing the personal access token
+    env_var: String,
+
+    #[arg(long = "skip-forks")]
+    #[serde(default)]
+    /// Don't clone forked repositories
+    skip_forks: bool,
+
+    #[arg(long = "include")]
+    #[serde(default)]
+    /// Only clone repositories that match these regular expressions. The repository name
+    /// includes the project name.
+    include: Vec<String>,
+
+    #[arg(long = "auth-http")]
+    #[serde(default)]
+    /// Use HTTP authentication instead of SSH
+    auth_http: bool,
+
+    #[arg(long = "exclude")]
+    #[serde(default)]
+    /// Don't clone repositories that match these regular expressions. The repository name
+    /// includes the project name.
+    exclude: Vec<String>,
+
+    #[arg(long = "url", default_value = DEFAULT_AZURE_DEVOPS_URL)]
+    #[serde(default = "public_azure_devops_url")]
+    /// Azure DevOps instance URL, if using Azure DevOps Server this should be
+    /// http(s)://HOSTNAME/tfs
+    pub url: String,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    template: PathTemplate,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    urls: CloneUrlOptions,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    token: TokenSource,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    pub http: HttpOptions,
+}
+
+impl fmt::Display for AzureDevOpsProvider {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Azure DevOps organization {} at {} in directory {}, using the token stored in {}",
+            style(&self.name).green(),
+            style(&self.url).green(),
+            style(&self.path).green(),
+            style(&self.env_var).green(),
+        )
+    }
+}
+
+impl Provider for AzureDevOpsProvider {
+    fn correctly_configured(&self) -> bool {
+        if !self.token.is_configured(&self.env_var) {
+            println!(
+                "{}",
+                style(format!(
+                    "Error: {} environment variable is not defined",
+                    self.env_var
+                ))
+                .red()
+            );
+            println!("Create a personal access token with the Code (Read) scope here:");
+            println!("{}/{}/_usersSettings/tokens", self.url, self.name);
+            println!(
+                "Then set a {} environment variable with the value",
+                style(&self.env_var).green()
+            );
+            return false;
+        }
+        if self.name.contains('/') {
+            println!(
+                "{}",
+                style("Error: Ensure that organization names do not contain forward slashes").red()
+            );
+            println!("You specified: {}", self.name);
+            return false;
+        }
+        true
+    }
+
+    fn fetch_repositories(&self, context: &FetchContext) -> anyhow::Result<Vec<Repository>> {
+        let azure_token = context.token(&self.token, &self.env_var, url_host(&self.url))?;
+        // Personal access tokens are sent as the password with an empty username
+        let auth_header = format!(
+            "Basic {}",
+            general_purpose::STANDARD.encode(format!(":{}", azure_token))
+        );
+
+        let include_regex_set = create_include_regex_set(&self.include)?;
+        let exclude_regex_set = create_exclude_regex_set(&self.exclude)?;
+
+        let client = context.client(&self.http, &self.token, &self.env_var)?;
+
+        let base_url = format!("{}/{}", self.url.trim_end_matches('/'), self.name);
+        // The repositories endpoint isn't paginated, so we need one request for the whole
+        // organization or one per project.
+        let urls: Vec<String> = if self.projects.is_empty() {
+            vec![format!(
+                "{}/_apis/git/repositories?api-version=7.1",
+                base_url
+            )]
+        } else {
+            self.projects
+                .iter()
+                .map(|project| {
+                    format!(
+                        "{}/{}/_apis/git/repositories?api-version=7.1",
+                        base_url, project
+                    )
+                })
+                .collect()
+        };
+
+        let mut repositories = Vec::new();
+
+        for url in urls {
+            let response: AzureDevOpsRepositoryList = client
+                .get(&url)
+                .set("Authorization", &auth_header)
+                .call()?
+                .into_json()
+                .with_context(|| format!("Invalid response from Azure DevOps for {}", url))?;
+
+            repositories.extend(
+                response
+                    .value
+                    .into_iter()
+                    .filter(|r| !r.is_disabled)
+                    .filter(|r| !self.skip_forks || !r.is_fork)
+                    .filter(|r| include_regex_set.is_match(&r.full_name()))
+                    .filter(|r| !exclude_regex_set.is_match(&r.full_name()))
+                    .map(|r| {
+                        let full_name = r.full_name();
+                        let url = self.urls.url(
+                            if self.auth_http {
+                                r.remote_url.clone()
+                            } else {
+                                r.ssh_url
+                            },
+                            &r.remote_url,
+                            &full_name,
+                        );
+                        Repository::new(
+                            self.template.path(&self.path, &r.remote_url, &full_name),
+                            url,
+                            r.default_branch
+                                .map(|b| b.trim_start_matches("refs/heads/").to_string()),
+                            None,
+                        )
+                        .with_id(r.id)
+                    }),
+            );
+        }
+
+        Ok(repositories)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::test_server::{json_response, request_path, serve};
+    use clap::Parser;
+    use serde_json::json;
+
+    fn repository(project: &str, name: &str) -> serde_json::Value {
+        json!({
+            "id": format!("{}-{}", project, name),
+            "name": name,
+            "project": {"name": project},
+            "defaultBranch": "refs/heads/main",
+            "remoteUrl": format!("https://dev.azure.com/my-org/{}/_git/{}", project, name),
+            "sshUrl": format!("git@ssh.dev.azure.com:v3/my-org/{}/{}", project, name),
+        })
+    }
+
+    fn fetch(args: &[&str], responses: Vec<String>) -> (Vec<Repository>, Vec<String>) {
+        let (url, handle) = serve(responses);
+        let provider = AzureDevOpsProvider::parse_from(
+            [
+                "azure-devops",
+                "my-org",
+                "--url",
+                &url,
+                "--token-command",
+                "echo secret",
+            ]
+            .iter()
+            .chain(args),
+        );
+        let context = FetchContext {
+            http: HttpOptions {
+                allow_http: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let repositories = provider.fetch_repositories(&context).unwrap();
+        (repositories, handle.join().unwrap())
+    }
+
+    #[test]
+    fn test_fetch_organization_repositories() {
+        let mut disabled = repository("web", "old");
+        disabled["isDisabled"] = json!(true);
+        let mut empty = repository("web", "empty");
+        empty.as_object_mut().unwrap().remove("defaultBranch");
+        let (repositories, requests) = fetch(
+            &[],
+            vec![json_response(json!({
+                "value": [repository("web", "site"), disabled, empty],
+            }))],
+        );
+        assert_eq!(
+            requests.iter().map(|r| request_path(r)).collect::<Vec<_>>(),
+            ["/my-org/_apis/git/repositories?api-version=7.1"]
+        );
+        // Personal access tokens are the password of an empty username
+        let credentials = general_purpose::STANDARD.encode(":secret");
+        assert!(requests[0].contains(&format!("Authorization: Basic {}\r\n", credentials)));
+        assert_eq!(repositories.len(), 2);
+        assert_eq!(repositories[0].name(), "azure/web/site");
+        assert_eq!(repositories[0].branch.as_deref(), Some("main"));
+        assert_eq!(
+            repositories[0].url(),
+            "git@ssh.dev.azure.com:v3/my-org/web/site"
+        );
+        assert_eq!(repositories[1].name(), "azure/web/empty");
+        assert_eq!(repositories[1].branch, None);
+    }
+
+    #[test]
+    fn test_fetch_project_repositories() {
+        let (repositories, requests) = fetch(
+            &["--project", "web", "--project", "api"],
+            vec![
+                json_response(json!({"value": [repository("web", "site")]})),
+                json_response(json!({"value": [repository("api", "service")]})),
+            ],
+        );
+        assert_eq!(
+            requests.iter().map(|r| request_path(r)).collect::<Vec<_>>(),
+            [
+                "/my-org/web/_apis/git/repositories?api-version=7.1",
+                "/my-org/api/_apis/git/repositories?api-version=7.1",
+            ]
+        );
+        assert_eq!(
+            repositories.iter().map(|r| r.name()).collect::<Vec<_>>(),
+            ["azure/web/site", "azure/api/service"]
+        );
+    }
+}
@@ -0,0 +1,324 @@
+use crate::providers::{
+    create_exclude_regex_set, create_include_regex_set, url_host, CloneUrlOptions, FetchContext,
+    HttpOptions, PathTemplate, Provider, TokenSource,
+};
+use crate::repository::Repository;
+use anyhow::Context;
+use base64::{engine::general_purpose, Engine};
+use console::style;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+#[derive(Deserialize, Debug)]
+struct BitbucketPage {
+    values: Vec<BitbucketRepository>,
+    next: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct BitbucketRepository {
+    uuid: String,
+    full_name: String,
+    mainbranch: Option<BitbucketBranch>,
+    links: BitbucketLinks,
+    parent: Option<serde_json::Value>,
+}
+
+#[derive(Deserialize, Debug)]
+struct BitbucketBranch {
+    name: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct BitbucketLinks {
+    clone: Vec<BitbucketCloneLink>,
+}
+
+#[derive(Deserialize, Debug)]
+struct BitbucketCloneLink {
+    name: String,
+    href: String,
+}
+
+impl BitbucketRepository {
+    fn clone_url(&self, protocol: &str) -> Option<String> {
+        self.links
+            .clone
+            .iter()
+            .find(|link| link.name == protocol)
+            .map(|link| link.href.clone())
+    }
+}
+
+fn default_env_var() -> String {
+    String::from("BITBUCKET_TOKEN")
+}
+
+static DEFAULT_BITBUCKET_URL: &str = "https://api.bitbucket.org";
+
+fn public_bitbucket_url() -> String {
+    DEFAULT_BITBUCKET_URL.to_string()
+}
+
+#[derive(Deserialize, Serialize, Default, Debug, Eq, Ord, PartialEq, PartialOrd, clap::Parser)]
+#[serde(rename_all = "lowercase")]
+#[command(about = "Add a Bitbucket Cloud workspace by name")]
+pub struct BitbucketProvider {
+    /// The name of the Bitbucket workspace to add
+    pub name: String,
+
+    #[arg(long = "path", default_value = "bitbucket")]
+    /// Clone repos to a specific path
+    path: String,
+
+    #[arg(long = "env-name", short = 'e', default_value = "BITBUCKET_TOKEN")]
+    #[serde(default = "default_env_var")]
+    /// Environment variable containing the auth token. Access tokens are sent as bearer
+    /// tokens, values in the form `username:app-password` use basic authentication.
+    env_var: String,
+
+    #[arg(long = "skip-forks")]
+    #[serde(default)]
+    /// Don't clone forked repositories
+    skip_forks: bool,
+
+    #[arg(long = "include")]
+    #[serde(default)]
+    /// Only clone repositories that match these regular expressions. The repository name
+    /// includes the workspace name.
+    include: Vec<String>,
+
+    #[arg(long = "auth-http")]
+    #[serde(default)]
+    /// Use HTTP authentication instead of SSH
+    auth_http: bool,
+
+    #[arg(long = "exclude")]
+    #[serde(default)]
+    /// Don't clone repositories that match these regular expressions. The repository name
+    /// includes the workspace name.
+    exclude: Vec<String>,
+
+    #[arg(long = "url", default_value = DEFAULT_BITBUCKET_URL)]
+    #[serde(default = "public_bitbucket_url")]
+    /// Bitbucket API URL
+    pub url: String,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    template: PathTemplate,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    urls: CloneUrlOptions,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    token: TokenSource,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    pub http: HttpOptions,
+}
+
+impl fmt::Display for BitbucketProvider {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Bitbucket workspace {} in directory {}, using the token stored in {}",
+            style(&self.name.to_lowercase()).green(),
+            style(&self.path).green(),
+            style(&self.env_var).green(),
+        )
+    }
+}
+
+impl Provider for BitbucketProvider {
+    fn correctly_configured(&self) -> bool {
+        if !self.token.is_configured(&self.env_var) {
+            println!(
+                "{}",
+                style(format!(
+                    "Error: {} environment variable is not defined",
+                    self.env_var
+                ))
+                .red()
+            );
+            println!(
+                "Create an access token in your Bitbucket workspace settings -> Access tokens"
+            );
+            println!(
+                "Then set a {} environment variable with the value",
+                style(&self.env_var).green()
+            );
+            return false;
+        }
+        if self.name.contains('/') {
+            println!(
+                "{}",
+                style("Error: Ensure that workspace names do not contain forward slashes").red()
+            );
+            println!("You specified: {}", self.name);
+            return false;
+        }
+        true
+    }
+
+    fn fetch_repositories(&self, context: &FetchContext) -> anyhow::Result<Vec<Repository>> {
+        let bitbucket_token = context.token(
+            &self.token,
+            &self.env_var,
+            url_host(&self.url).trim_start_matches("api."),
+        )?;
+
+        // Workspace access tokens are bearer tokens, app passwords need to be sent along with
+        // the username using basic auth.
+        let auth_header = if bitbucket_token.contains(':') {
+            format!(
+                "Basic {}",
+                general_purpose::STANDARD.encode(bitbucket_token.as_bytes())
+            )
+        } else {
+            format!("Bearer {}", bitbucket_token)
+        };
+
+        let include_regex_set = create_include_regex_set(&self.include)?;
+        let exclude_regex_set = create_exclude_regex_set(&self.exclude)?;
+
+        let client = context.client(&self.http, &self.token, &self.env_var)?;
+
+        let mut repositories = Vec::new();
+        let mut next_url = Some(format!(
+            "{}/2.0/repositories/{}?pagelen=100",
+            self.url, self.name
+        ));
+
+        // Bitbucket returns the full URL of the next page, so we just follow it until it's gone.
+        while let Some(url) = next_url {
+            let page: BitbucketPage = client
+                .get(&url)
+                .set("Authorization", &auth_header)
+                .call()?
+                .into_json()?;
+
+            repositories.extend(
+                page.values
+                    .into_iter()
+                    .filter(|r| !self.skip_forks || r.parent.is_none())
+                    .filter(|r| include_regex_set.is_match(&r.full_name))
+                    .filter(|r| !exclude_regex_set.is_match(&r.full_name))
+                    .map(|r| {
+                        let protocol = if self.auth_http { "https" } else { "ssh" };
+                        let url = r.clone_url(protocol).with_context(|| {
+                            format!("Repository {} has no {} clone URL", r.full_name, protocol)
+                        })?;
+                        // The API lives on a different host, so use the HTTPS clone URL
+                        let https_url = r.clone_url("https").unwrap_or_default();
+                        Ok(Repository::new(
+                            self.template.path(&self.path, &https_url, &r.full_name),
+                            self.urls.url(url, &https_url, &r.full_name),
+                            r.mainbranch.map(|b| b.name),
+                            None,
+                        )
+                        .with_id(r.uuid))
+                    })
+                    .collect::<anyhow::Result<Vec<_>>>()?,
+            );
+
+            next_url = page.next;
+        }
+
+        Ok(repositories)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::test_server::{json_response, request_path, serve};
+    use clap::Parser;
+    use serde_json::json;
+
+    fn repository(name: &str, forked: bool) -> serde_json::Value {
+        json!({
+            "uuid": format!("{{{}}}", name),
+            "full_name": format!("my-workspace/{}", name),
+            "mainbranch": {"name": "main"},
+            "links": {"clone": [
+                {"name": "https", "href": format!("https://bitbucket.org/my-workspace/{}.git", name)},
+                {"name": "ssh", "href": format!("git@bitbucket.org:my-workspace/{}.git", name)},
+            ]},
+            "parent": if forked { json!({"full_name": "other/repo"}) } else { json!(null) },
+        })
+    }
+
+    fn fetch(args: &[&str], responses: Vec<String>) -> (Vec<Repository>, Vec<String>) {
+        let (url, handle) = serve(responses);
+        let provider = BitbucketProvider::parse_from(
+            ["bitbucket", "my-workspace", "--url", &url]
+                .iter()
+                .chain(args),
+        );
+        let context = FetchContext {
+            http: HttpOptions {
+                allow_http: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let repositories = provider.fetch_repositories(&context).unwrap();
+        (repositories, handle.join().unwrap())
+    }
+
+    #[test]
+    fn test_fetch_repositories_follows_next_pages() {
+        let (repositories, requests) = fetch(
+            &["--token-command", "echo secret"],
+            vec![
+                json_response(json!({
+                    "values": [repository("one", false)],
+                    "next": "{url}/2.0/repositories/my-workspace?pagelen=100&page=2",
+                })),
+                json_response(json!({"values": [repository("two", true)]})),
+            ],
+        );
+        assert_eq!(
+            requests.iter().map(|r| request_path(r)).collect::<Vec<_>>(),
+            [
+                "/2.0/repositories/my-workspace?pagelen=100",
+                "/2.0/repositories/my-workspace?pagelen=100&page=2",
+            ]
+        );
+        // Access tokens are bearer tokens
+        assert!(requests[0].contains("Authorization: Bearer secret\r\n"));
+        assert_eq!(repositories.len(), 2);
+        assert_eq!(repositories[0].name(), "bitbucket/my-workspace/one");
+        assert_eq!(repositories[0].id(), Some("{one}"));
+        assert_eq!(
+            repositories[1].url(),
+            "git@bitbucket.org:my-workspace/two.git"
+        );
+    }
+
+    #[test]
+    fn test_fetch_repositories_with_app_password_over_https_without_forks() {
+        let (repositories, requests) = fetch(
+            &[
+                "--token-command",
+                "echo jdoe:app-password",
+                "--auth-http",
+                "--skip-forks",
+            ],
+            vec![json_response(json!({
+                "values": [repository("one", false), repository("fork", true)],
+            }))],
+        );
+        let credentials = general_purpose::STANDARD.encode("jdoe:app-password");
+        assert!(requests[0].contains(&format!("Authorization: Basic {}\r\n", credentials)));
+        assert_eq!(repositories.len(), 1);
+        assert_eq!(
+            repositories[0].url(),
+            "https://bitbucket.org/my-workspace/one.git"
+        );
+    }
+}
@@ -0,0 +1,364 @@
+use crate::providers::{
+    create_exclude_regex_set, create_include_regex_set, url_host, ArchivedOptions, CloneUrlOptions,
+    FetchContext, HttpClient, HttpOptions, PathTemplate, Provider, TokenSource,
+};
+use crate::repository::Repository;
+use anyhow::Context;
+use console::style;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct BitbucketServerPage {
+    values: Vec<BitbucketServerRepository>,
+    is_last_page: bool,
+    next_page_start: Option<u64>,
+}
+
+#[derive(Deserialize, Debug)]
+struct BitbucketServerRepository {
+    id: u64,
+    slug: String,
+    project: BitbucketServerProject,
+    links: BitbucketServerLinks,
+    #[serde(default)]
+    archived: bool,
+    origin: Option<serde_json::Value>,
+}
+
+#[derive(Deserialize, Debug)]
+struct BitbucketServerProject {
+    key: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct BitbucketServerLinks {
+    clone: Vec<BitbucketServerCloneLink>,
+}
+
+#[derive(Deserialize, Debug)]
+struct BitbucketServerCloneLink {
+    name: String,
+    href: String,
+}
+
+impl BitbucketServerRepository {
+    fn full_name(&self) -> String {
+        format!("{}/{}", self.project.key, self.slug)
+    }
+
+    fn clone_url(&self, protocol: &str) -> Option<String> {
+        self.links
+            .clone
+            .iter()
+            .find(|link| link.name == protocol)
+            .map(|link| link.href.clone())
+    }
+}
+
+fn default_env_var() -> String {
+    String::from("BITBUCKET_SERVER_TOKEN")
+}
+
+#[derive(Deserialize, Serialize, Debug, Eq, Ord, PartialEq, PartialOrd, clap::Parser)]
+#[serde(rename_all = "lowercase")]
+#[command(about = "Add a Bitbucket Server or Data Center project or user by name")]
+pub struct BitbucketServerProvider {
+    /// The project key to add. Personal repositories can be added by prefixing the user
+    /// slug with a tilde, e.g. `~jdoe`
+    pub name: String,
+
+    #[arg(long = "url")]
+    /// Bitbucket Server instance URL, e.g. https://bitbucket.company.com
+    pub url: String,
+
+    #[arg(long = "path", default_value = "bitbucket")]
+    /// Clone repos to a specific path
+    path: String,
+
+    #[arg(
+        long = "env-name",
+        short = 'e',
+        default_value = "BITBUCKET_SERVER_TOKEN"
+    )]
+    #[serde(default = "default_env_var")]
+    /// Environment variable containing the HTTP access token
+    env_var: String,
+
+    #[arg(long = "skip-forks")]
+    #[serde(default)]
+    /// Don't clone forked repositories
+    skip_forks: bool,
+
+    #[arg(long = "include")]
+    #[serde(default)]
+    /// Only clone repositories that match these regular expressions. The repository name
+    /// includes the project key.
+    include: Vec<String>,
+
+    #[arg(long = "auth-http")]
+    #[serde(default)]
+    /// Use HTTP authentication instead of SSH
+    auth_http: bool,
+
+    #[arg(long = "exclude")]
+    #[serde(default)]
+    /// Don't clone repositories that match these regular expressions. The repository name
+    /// includes the project key.
+    exclude: Vec<String>,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    archived: ArchivedOptions,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    template: PathTemplate,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    urls: CloneUrlOptions,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    token: TokenSource,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    pub http: HttpOptions,
+}
+
+impl fmt::Display for BitbucketServerProvider {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Bitbucket Server project {} at {} in directory {}, using the token stored in {}",
+            style(&self.name).green(),
+            style(&self.url).green(),
+            style(&self.path).green(),
+            style(&self.env_var).green(),
+        )
+    }
+}
+
+impl BitbucketServerProvider {
+    fn fetch_repositories_with(
+        &self,
+        client: &HttpClient,
+        token: &str,
+    ) -> anyhow::Result<Vec<Repository>> {
+        let include_regex_set = create_include_regex_set(&self.include)?;
+        let exclude_regex_set = create_exclude_regex_set(&self.exclude)?;
+
+        let protocol = if self.auth_http { "http" } else { "ssh" };
+        let mut start = 0;
+        let mut repositories = Vec::new();
+
+        loop {
+            let url = format!(
+                "{}/rest/api/1.0/projects/{}/repos?start={}&limit=100",
+                self.url.trim_end_matches('/'),
+                self.name,
+                start
+            );
+
+            let page: BitbucketServerPage = client
+                .get(&url)
+                .set("Authorization", &format!("Bearer {}", token))
+                .call()?
+                .into_json()?;
+
+            for repo in page.values {
+                let full_name = repo.full_name();
+                if !self.archived.is_match(repo.archived)
+                    || (self.skip_forks && repo.origin.is_some())
+                    || !include_regex_set.is_match(&full_name)
+                    || exclude_regex_set.is_match(&full_name)
+                {
+                    continue;
+                }
+                let url = repo.clone_url(protocol).with_context(|| {
+                    format!("Repository {} has no {} clone URL", full_name, protocol)
+                })?;
+                // The default branch isn't part of the repository listing, and git will
+                // check out whatever the server considers to be the default on clone anyway.
+                repositories.push(
+                    Repository::new(
+                        self.archived.path(
+                            repo.archived,
+                            self.template.path(&self.path, &self.url, &full_name),
+                        ),
+                        self.urls.url(url, &self.url, &full_name),
+                        None,
+                        None,
+                    )
+                    .with_archived(repo.archived)
+                    .with_id(repo.id.to_string()),
+                );
+            }
+
+            match (page.is_last_page, page.next_page_start) {
+                (false, Some(next_page_start)) => start = next_page_start,
+                _ => break,
+            }
+        }
+
+        Ok(repositories)
+    }
+}
+
+impl Provider for BitbucketServerProvider {
+    fn correctly_configured(&self) -> bool {
+        if !self.token.is_configured(&self.env_var) {
+            println!(
+                "{}",
+                style(format!(
+                    "Error: {} environment variable is not defined",
+                    self.env_var
+                ))
+                .red()
+            );
+            println!("Create an HTTP access token in your Bitbucket account -> HTTP access tokens");
+            println!(
+                "Then set a {} environment variable with the value",
+                style(&self.env_var).green()
+            );
+            return false;
+        }
+        if self.name.contains('/') {
+            println!(
+                "{}",
+                style("Error: Ensure that project keys do not contain forward slashes").red()
+            );
+            println!("You specified: {}", self.name);
+            return false;
+        }
+        true
+    }
+
+    fn fetch_repositories(&self, context: &FetchContext) -> anyhow::Result<Vec<Repository>> {
+        let token = context.token(&self.token, &self.env_var, url_host(&self.url))?;
+
+        let client = context.client(&self.http, &self.token, &self.env_var)?;
+
+        self.fetch_repositories_with(&client, &token)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::test_server::{json_response, request_path, serve};
+    use crate::providers::RateLimits;
+
+    fn repository_json(id: u64, key: &str, slug: &str, fork: bool) -> serde_json::Value {
+        let mut repo = serde_json::json!({
+            "id": id,
+            "slug": slug,
+            "project": {"key": key},
+            "links": {"clone": [
+                {"name": "http", "href": format!("https://bitbucket.local/scm/{}/{}.git", key, slug)},
+                {"name": "ssh", "href": format!("ssh://git@bitbucket.local:7999/{}/{}.git", key, slug)},
+            ]},
+        });
+        if fork {
+            repo["origin"] = serde_json::json!({"slug": "upstream"});
+        }
+        repo
+    }
+
+    fn provider(url: String, auth_http: bool, skip_forks: bool) -> BitbucketServerProvider {
+        BitbucketServerProvider {
+            name: "PRJ".to_string(),
+            url,
+            path: "bitbucket".to_string(),
+            env_var: default_env_var(),
+            skip_forks,
+            include: vec![],
+            auth_http,
+            exclude: vec![],
+            archived: ArchivedOptions::default(),
+            template: PathTemplate::default(),
+            urls: CloneUrlOptions::default(),
+            token: TokenSource::default(),
+            http: HttpOptions::default(),
+        }
+    }
+
+    #[test]
+    fn test_fetch_repositories_paginates() {
+        let (url, handle) = serve(vec![
+            json_response(serde_json::json!({
+                "values": [repository_json(1, "PRJ", "one", false)],
+                "isLastPage": false,
+                "nextPageStart": 1,
+            })),
+            json_response(serde_json::json!({
+                "values": [repository_json(2, "PRJ", "two", true)],
+                "isLastPage": true,
+            })),
+        ]);
+        let rate_limits = RateLimits::default();
+        let client = HttpClient::new(ureq::AgentBuilder::new().build(), &rate_limits);
+        let repositories = provider(url, false, false)
+            .fetch_repositories_with(&client, "token")
+            .unwrap();
+
+        let requests = handle.join().unwrap();
+        assert_eq!(
+            requests.iter().map(|r| request_path(r)).collect::<Vec<_>>(),
+            vec![
+                "/rest/api/1.0/projects/PRJ/repos?start=0&limit=100",
+                "/rest/api/1.0/projects/PRJ/repos?start=1&limit=100",
+            ]
+        );
+        assert_eq!(
+            repositories,
+            vec![
+                Repository::new(
+                    "bitbucket/PRJ/one".to_string(),
+                    "ssh://git@bitbucket.local:7999/PRJ/one.git".to_string(),
+                    None,
+                    None,
+                )
+                .with_id("1".to_string()),
+                Repository::new(
+                    "bitbucket/PRJ/two".to_string(),
+                    "ssh://git@bitbucket.local:7999/PRJ/two.git".to_string(),
+                    None,
+                    None,
+                )
+                .with_id("2".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_fetch_repositories_http_and_skip_forks() {
+        let (url, handle) = serve(vec![json_response(serde_json::json!({
+            "values": [
+                repository_json(1, "PRJ", "one", false),
+                repository_json(2, "PRJ", "two", true),
+            ],
+            "isLastPage": true,
+        }))]);
+        let rate_limits = RateLimits::default();
+        let client = HttpClient::new(ureq::AgentBuilder::new().build(), &rate_limits);
+        let repositories = provider(url, true, true)
+            .fetch_repositories_with(&client, "token")
+            .unwrap();
+        handle.join().unwrap();
+
+        assert_eq!(
+            repositories,
+            vec![Repository::new(
+                "bitbucket/PRJ/one".to_string(),
+                "https://bitbucket.local/scm/PRJ/one.git".to_string(),
+                None,
+                None,
+            )
+            .with_id("1".to_string())]
+        );
+    }
+}
@@ -0,0 +1,216 @@
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+// Headers that describe how the body was sent rather than the body itself. The cached body is
+// stored decoded, so these would be wrong when the response is rebuilt.
+const TRANSPORT_HEADERS: [&str; 4] = [
+    "connection",
+    "content-encoding",
+    "content-length",
+    "transfer-encoding",
+];
+
+/// Provider API responses stored on disk, under `.git-workspace/cache` in the workspace.
+/// GET responses with an ETag or Last-Modified header are revalidated with a conditional request,
+/// which most APIs don't count against the rate limit. In offline mode no requests are sent
+/// and every response has to come from the cache.
+#[derive(Debug)]
+pub struct ResponseCache {
+    directory: PathBuf,
+    offline: bool,
+}
+
+impl ResponseCache {
+    pub fn new(workspace: &Path, offline: bool) -> ResponseCache {
+        ResponseCache {
+            directory: workspace.join(".git-workspace").join("cache"),
+            offline,
+        }
+    }
+
+    pub fn is_offline(&self) -> bool {
+        self.offline
+    }
+
+    /// The cache key of a request. Request bodies are included, as GraphQL APIs send every
+    /// query to the same URL. `scope` keeps apart the responses of providers that send the same
+    /// requests with different tokens.
+    pub fn key(scope: &str, method: &str, url: &str, body: &str) -> String {
+        let mut hasher = Fnv1a::default();
+        for part in [scope, method, url, body] {
+            hasher.write(part.as_bytes());
+            // Keep ("a", "bc") and ("ab", "c") apart
+            hasher.write(&[0]);
+        }
+        format!("{:016x}", hasher.0)
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.directory.join(format!("{}.json", key))
+    }
+
+    pub fn load(&self, key: &str) -> Option<CachedResponse> {
+        let contents = fs::read_to_string(self.path(key)).ok()?;
+        // A corrupt entry is treated like a missing one, and overwritten by the next response
+        serde_json::from_str(&contents).ok()
+    }
+
+    pub fn store(&self, key: &str, response: &CachedResponse) -> io::Result<()> {
+        fs::create_dir_all(&self.directory)?;
+        // Providers are fetched in parallel, so write to a temporary file and rename it into
+        // place to never leave a half written entry behind.
+        let path = self.path(key);
+        let temporary_path = path.with_extension(format!("{}.tmp", std::process::id()));
+        fs::write(&temporary_path, serde_json::to_vec(response)?)?;
+        fs::rename(&temporary_path, &path)
+    }
+}
+
+/// A successful response, with everything needed to rebuild it
+#[derive(Deserialize, Serialize, Debug, Eq, PartialEq)]
+pub struct CachedResponse {
+    url: String,
+    status: u16,
+    status_text: String,
+    headers: Vec<(String, String)>,
+    body: String,
+}
+
+impl CachedResponse {
+    /// Read a response's body so it can be cached
+    pub fn read(url: &str, response: ureq::Response) -> io::Result<CachedResponse> {
+        let status = response.status();
+        let status_text = response.status_text().to_string();
+        let mut names = response.headers_names();
+        // Repeated headers are listed once per value, but `all` returns every value
+        names.sort();
+        names.dedup();
+        let headers = names
+            .into_iter()
+            .filter(|name| !TRANSPORT_HEADERS.contains(&name.as_str()))
+            .flat_map(|name| {
+                response
+                    .all(&name)
+                    .into_iter()
+                    .map(|value| (name.clone(), value.to_string()))
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        let body = response.into_string()?;
+        Ok(CachedResponse {
+            url: url.to_string(),
+            status,
+            status_text,
+            headers,
+            body,
+        })
+    }
+
+    pub fn etag(&self) -> Option<&str> {
+        self.header("etag")
+    }
+
+    pub fn last_modified(&self) -> Option<&str> {
+        self.header("last-modified")
+    }
+
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    // Returns ureq's error, like the rest of `HttpClient`
+    #[allow(clippy::result_large_err)]
+    pub fn to_response(&self) -> Result<ureq::Response, ureq::Error> {
+        let mut raw = format!("HTTP/1.1 {} {}\r\n", self.status, self.status_text);
+        for (name, value) in &self.headers {
+            raw.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        raw.push_str(&format!("content-length: {}\r\n\r\n", self.body.len()));
+        raw.push_str(&self.body);
+        raw.parse()
+    }
+}
+
+/// The 64 bit FNV-1a hash. This is stable across Rust versions and platforms, unlike the
+/// standard library's hashers, so cache keys stay valid after an upgrade.
+struct Fnv1a(u64);
+
+impl Default for Fnv1a {
+    fn default() -> Self {
+        Fnv1a(0xcbf29ce484222325)
+    }
+}
+
+impl Fnv1a {
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= u64::from(*byte);
+            self.0 = self.0.wrapping_mul(0x100000001b3);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_key() {
+        let key = ResponseCache::key("", "GET", "https://api.github.com/orgs/orf", "");
+        assert_eq!(key.len(), 16);
+        assert_eq!(
+            key,
+            ResponseCache::key("", "GET", "https://api.github.com/orgs/orf", "")
+        );
+        assert_ne!(
+            key,
+            ResponseCache::key("", "POST", "https://api.github.com/orgs/orf", "")
+        );
+        assert_ne!(
+            key,
+            ResponseCache::key(
+                "env:GITHUB_TOKEN",
+                "GET",
+                "https://api.github.com/orgs/orf",
+                ""
+            )
+        );
+        assert_ne!(
+            ResponseCache::key("", "POST", "https://a", "bc"),
+            ResponseCache::key("", "POST", "https://ab", "c")
+        );
+    }
+
+    #[test]
+    fn test_store_and_load() {
+        let temp_dir = TempDir::new().unwrap();
+        let cache = ResponseCache::new(temp_dir.path(), false);
+        let key = ResponseCache::key("", "GET", "https://gitlab.com/api/v4/projects", "");
+        assert_eq!(cache.load(&key), None);
+
+        let response: ureq::Response = "HTTP/1.1 200 OK\r\n\
+            ETag: \"abc\"\r\n\
+            X-Next-Page: 2\r\n\
+            Content-Length: 2\r\n\
+            \r\n\
+            []"
+        .parse()
+        .unwrap();
+        let cached = CachedResponse::read("https://gitlab.com/api/v4/projects", response).unwrap();
+        assert_eq!(cached.etag(), Some("\"abc\""));
+        assert_eq!(cached.last_modified(), None);
+        cache.store(&key, &cached).unwrap();
+
+        let loaded = cache.load(&key).unwrap();
+        assert_eq!(loaded, cached);
+        let response = loaded.to_response().unwrap();
+        assert_eq!(response.header("x-next-page"), Some("2"));
+        assert_eq!(response.into_string().unwrap(), "[]");
+    }
+}
@@ -0,0 +1,450 @@
+use crate::providers::{
+    create_exclude_regex_set, create_include_regex_set, url_host, ArchivedOptions, CloneUrlOptions,
+    FetchContext, HttpOptions, HttpRequest, PathTemplate, Provider, TokenSource,
+};
+use crate::repository::Repository;
+use anyhow::Context;
+use base64::{engine::general_purpose, Engine};
+use console::style;
+use rayon::prelude::*;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+
+// Gerrit prefixes every JSON response with this to prevent cross-site script inclusion
+static XSSI_PREFIX: &str = ")]}'";
+
+#[derive(Deserialize, Debug)]
+struct GerritProject {
+    // The URL encoded project name
+    id: String,
+    #[serde(default)]
+    state: Option<String>,
+}
+
+fn parse_response<T: DeserializeOwned>(body: &str) -> anyhow::Result<T> {
+    let json = body.trim_start().trim_start_matches(XSSI_PREFIX);
+    serde_json::from_str(json).with_context(|| format!("Invalid response from Gerrit: {}", body))
+}
+
+// Project names are used as a path segment, so slashes need to be encoded too.
+fn encode_project_name(name: &str) -> String {
+    name.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+fn default_env_var() -> String {
+    String::from("GERRIT_PASSWORD")
+}
+
+fn default_ssh_port() -> u16 {
+    29418
+}
+
+#[derive(Deserialize, Serialize, Debug, Eq, Ord, PartialEq, PartialOrd, clap::Parser)]
+#[serde(rename_all = "lowercase")]
+#[command(about = "Add projects from a Gerrit instance")]
+pub struct GerritProvider {
+    /// The URL of the Gerrit instance, e.g. https://review.company.com
+    pub url: String,
+
+    #[arg(long = "path", default_value = "gerrit")]
+    /// Clone repos to a specific path
+    path: String,
+
+    #[arg(long = "username")]
+    #[serde(default)]
+    /// Username to authenticate with. Without a username only anonymously visible projects
+    /// are listed.
+    username: Option<String>,
+
+    #[arg(long = "env-name", short = 'e', default_value = "GERRIT_PASSWORD")]
+    #[serde(default = "default_env_var")]
+    /// Environment variable containing the HTTP password of the user
+    env_var: String,
+
+    #[arg(long = "prefix")]
+    #[serde(default)]
+    /// Only list projects that start with this prefix. This is filtered by Gerrit.
+    prefix: Option<String>,
+
+    #[arg(long = "regex")]
+    #[serde(default)]
+    /// Only list projects that match this regular expression. This is filtered by Gerrit.
+    regex: Option<String>,
+
+    #[arg(long = "include")]
+    #[serde(default)]
+    /// Only clone repositories that match these regular expressions
+    include: Vec<String>,
+
+    #[arg(long = "auth-http")]
+    #[serde(default)]
+    /// Use HTTP authentication instead of SSH
+    auth_http: bool,
+
+    #[arg(long = "exclude")]
+    #[serde(default)]
+    /// Don't clone repositories that match these regular expressions
+    exclude: Vec<String>,
+
+    #[arg(long = "ssh-port", default_value = "29418")]
+    #[serde(default = "default_ssh_port")]
+    /// The port of the Gerrit SSH daemon
+    ssh_port: u16,
+
+    #[arg(long = "commit-msg-hook")]
+    #[serde(default)]
+    /// Install the Gerrit commit-msg hook, which adds a Change-Id to commits, after cloning.
+    /// The hook is downloaded from Gerrit for every new clone.
+    commit_msg_hook: bool,
+
+    #[arg(long = "skip-head-lookup")]
+    #[serde(default)]
+    /// Don't ask Gerrit which branch HEAD points to. The project listing doesn't include it, so
+    /// this otherwise takes one request per project, which adds up on large instances. Clones
+    /// then check out the server's HEAD, and `switch-and-pull` leaves the branch alone.
+    skip_head_lookup: bool,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    archived: ArchivedOptions,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    template: PathTemplate,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    urls: CloneUrlOptions,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    token: TokenSource,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    pub http: HttpOptions,
+}
+
+impl fmt::Display for GerritProvider {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Gerrit projects at {} in directory {}",
+            style(&self.url).green(),
+            style(&self.path).green(),
+        )?;
+        if let Some(username) = &self.username {
+            write!(
+                f,
+                ", as {} using the password stored in {}",
+                style(username).green(),
+                style(&self.env_var).green()
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl GerritProvider {
+    fn base_url(&self) -> &str {
+        self.url.trim_end_matches('/')
+    }
+
+    /// The filters that tell providers for the same instance apart, for the provider id
+    pub fn id_qualifiers(&self) -> Vec<(&'static str, String)> {
+        [("prefix", &self.prefix), ("regex", &self.regex)]
+            .into_iter()
+            .filter_map(|(key, value)| Some((key, value.clone()?)))
+            .collect()
+    }
+
+    // Authenticated REST and git requests need to be made below /a/
+    fn authenticated_url(&self) -> String {
+        match self.username {
+            Some(_) => format!("{}/a", self.base_url()),
+            None => self.base_url().to_string(),
+        }
+    }
+
+    fn clone_url(&self, project: &str) -> String {
+        if self.auth_http {
+            return format!("{}/{}", self.authenticated_url(), project);
+        }
+        let without_scheme = self
+            .base_url()
+            .split("://")
+            .last()
+            .unwrap_or(self.base_url());
+        let host_and_port = without_scheme.split('/').next().unwrap_or(without_scheme);
+        let host = host_and_port.split(':').next().unwrap_or(host_and_port);
+        match &self.username {
+            Some(username) => format!("ssh://{}@{}:{}/{}", username, host, self.ssh_port, project),
+            None => format!("ssh://{}:{}/{}", host, self.ssh_port, project),
+        }
+    }
+
+    fn get<T: DeserializeOwned>(
+        &self,
+        request: HttpRequest,
+        auth_header: &Option<String>,
+    ) -> anyhow::Result<T> {
+        let request = match auth_header {
+            Some(header) => request.set("Authorization", header),
+            None => request,
+        };
+        let body = request.call()?.into_string()?;
+        parse_response(&body)
+    }
+}
+
+impl Provider for GerritProvider {
+    fn correctly_configured(&self) -> bool {
+        if self.username.is_some() && !self.token.is_configured(&self.env_var) {
+            println!(
+                "{}",
+                style(format!(
+                    "Error: {} environment variable is not defined",
+                    self.env_var
+                ))
+                .red()
+            );
+            println!("Generate an HTTP password here:");
+            println!("{}/settings/#HTTPCredentials", self.base_url());
+            println!(
+                "Then set a {} environment variable with the value",
+                style(&self.env_var).green()
+            );
+            return false;
+        }
+        if self.prefix.is_some() && self.regex.is_some() {
+            println!(
+                "{}",
+                style("Error: Gerrit does not support using a prefix and a regex together").red()
+            );
+            return false;
+        }
+        true
+    }
+
+    fn fetch_repositories(&self, context: &FetchContext) -> anyhow::Result<Vec<Repository>> {
+        let auth_header = match &self.username {
+            Some(username) => {
+                let password = context.token(&self.token, &self.env_var, url_host(&self.url))?;
+                Some(format!(
+                    "Basic {}",
+                    general_purpose::STANDARD.encode(format!("{}:{}", username, password))
+                ))
+            }
+            None => None,
+        };
+
+        let include_regex_set = create_include_regex_set(&self.include)?;
+        let exclude_regex_set = create_exclude_regex_set(&self.exclude)?;
+
+        let client = context.client(&self.http, &self.token, &self.env_var)?;
+
+        // Only list projects with a HEAD, which skips permission-only projects like All-Projects
+        let mut request = client.get(&format!("{}/projects/?d&b=HEAD", self.authenticated_url()));
+        if let Some(prefix) = &self.prefix {
+            request = request.query("p", prefix);
+        }
+        if let Some(regex) = &self.regex {
+            request = request.query("r", regex);
+        }
+        let projects: BTreeMap<String, GerritProject> = self.get(request, &auth_header)?;
+
+        let projects: Vec<(String, String, bool)> = projects
+            .into_iter()
+            // Hidden projects can't be cloned, and read-only projects are Gerrit's equivalent
+            // of an archived repository
+            .filter(|(_, project)| project.state.as_deref() != Some("HIDDEN"))
+            .map(|(name, project)| {
+                let is_archived = project.state.as_deref() == Some("READ_ONLY");
+                (name, project.id, is_archived)
+            })
+            .filter(|(_, _, is_archived)| self.archived.is_match(*is_archived))
+            .filter(|(name, _, _)| include_regex_set.is_match(name))
+            .filter(|(name, _, _)| !exclude_regex_set.is_match(name))
+            .collect();
+
+        // The project listing doesn't include which branch HEAD points to, so we need to ask
+        // for each project individually.
+        projects
+            .par_iter()
+            .map(|(name, id, is_archived)| {
+                let branch = if self.skip_head_lookup {
+                    None
+                } else {
+                    let head_url = format!(
+                        "{}/projects/{}/HEAD",
+                        self.authenticated_url(),
+                        encode_project_name(name)
+                    );
+                    let head: String = self
+                        .get(client.get(&head_url), &auth_header)
+                        .with_context(|| format!("Error fetching HEAD of project {}", name))?;
+                    Some(head.trim_start_matches("refs/heads/").to_string())
+                };
+                let mut repository = Repository::new(
+                    self.archived.path(
+                        *is_archived,
+                        self.template.path(&self.path, &self.url, name),
+                    ),
+                    self.urls.url(self.clone_url(name), &self.url, name),
+                    branch,
+                    None,
+                )
+                .with_archived(*is_archived)
+                .with_id(id.clone());
+                if self.commit_msg_hook {
+                    repository = repository.with_commit_msg_hook(format!(
+                        "{}/tools/hooks/commit-msg",
+                        self.base_url()
+                    ));
+                }
+                Ok(repository)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lockfile::Lockfile;
+    use crate::providers::test_server::serve;
+    use tempfile::TempDir;
+
+    fn provider(username: Option<&str>, auth_http: bool) -> GerritProvider {
+        GerritProvider {
+            url: "https://review.example.com/".to_string(),
+            path: "gerrit".to_string(),
+            username: username.map(String::from),
+            env_var: default_env_var(),
+            prefix: None,
+            regex: None,
+            include: vec![],
+            auth_http,
+            exclude: vec![],
+            ssh_port: default_ssh_port(),
+            commit_msg_hook: false,
+            skip_head_lookup: false,
+            archived: ArchivedOptions::default(),
+            template: PathTemplate::default(),
+            urls: CloneUrlOptions::default(),
+            token: TokenSource::default(),
+            http: HttpOptions::default(),
+        }
+    }
+
+    #[test]
+    fn test_fetch_repositories_with_commit_msg_hook() {
+        let (url, handle) = serve(vec![
+            "HTTP/1.1 200 OK\r\n\r\n)]}'\n{\"tools/gerrit\": {\"id\": \"tools%2Fgerrit\"}}"
+                .to_string(),
+            "HTTP/1.1 200 OK\r\n\r\n)]}'\n\"refs/heads/main\"".to_string(),
+        ]);
+        let provider = GerritProvider {
+            url: url.clone(),
+            commit_msg_hook: true,
+            http: HttpOptions {
+                allow_http: true,
+                ..Default::default()
+            },
+            ..provider(None, true)
+        };
+        let repositories = provider
+            .fetch_repositories(&FetchContext::default())
+            .unwrap();
+        handle.join().unwrap();
+
+        // Only the hook's URL is kept in the lockfile, `update` reads the HTTP options from the
+        // configuration when it downloads the hook
+        let temp_dir = TempDir::new().unwrap();
+        let lockfile = Lockfile::new(temp_dir.path().join("workspace-lock.toml"));
+        lockfile.write(&repositories).unwrap();
+        let contents =
+            std::fs::read_to_string(temp_dir.path().join("workspace-lock.toml")).unwrap();
+        assert!(
+            contents.contains(&format!(
+                "commit_msg_hook = \"{}/tools/hooks/commit-msg\"",
+                url
+            )),
+            "{}",
+            contents
+        );
+        assert!(!contents.contains("allow_http"), "{}", contents);
+        assert_eq!(lockfile.read().unwrap(), repositories);
+        assert_eq!(repositories[0].id(), Some("tools%2Fgerrit"));
+    }
+
+    #[test]
+    fn test_fetch_repositories_without_head_lookup() {
+        let (url, handle) = serve(vec![
+            "HTTP/1.1 200 OK\r\n\r\n)]}'\n{\"tools/gerrit\": {\"id\": \"tools%2Fgerrit\"}}"
+                .to_string(),
+        ]);
+        let provider = GerritProvider {
+            url,
+            skip_head_lookup: true,
+            http: HttpOptions {
+                allow_http: true,
+                ..Default::default()
+            },
+            ..provider(None, true)
+        };
+        let repositories = provider
+            .fetch_repositories(&FetchContext::default())
+            .unwrap();
+        // Only the project listing is requested
+        assert_eq!(handle.join().unwrap().len(), 1);
+        assert_eq!(repositories[0].branch, None);
+    }
+
+    #[test]
+    fn test_parse_response_strips_xssi_prefix() {
+        let projects: BTreeMap<String, GerritProject> = parse_response(
+            ")]}'\n{\"tools/gerrit\": {\"id\": \"tools%2Fgerrit\", \"state\": \"READ_ONLY\"}}",
+        )
+        .unwrap();
+        assert_eq!(projects["tools/gerrit"].state.as_deref(), Some("READ_ONLY"));
+
+        let head: String = parse_response(")]}'\n\"refs/heads/main\"").unwrap();
+        assert_eq!(head, "refs/heads/main");
+    }
+
+    #[test]
+    fn test_encode_project_name() {
+        assert_eq!(encode_project_name("tools/gerrit"), "tools%2Fgerrit");
+        assert_eq!(encode_project_name("plain-name_1.0"), "plain-name_1.0");
+    }
+
+    #[test]
+    fn test_clone_url() {
+        assert_eq!(
+            provider(None, false).clone_url("tools/gerrit"),
+            "ssh://review.example.com:29418/tools/gerrit"
+        );
+        assert_eq!(
+            provider(Some("jdoe"), false).clone_url("tools/gerrit"),
+            "ssh://jdoe@review.example.com:29418/tools/gerrit"
+        );
+        assert_eq!(
+            provider(None, true).clone_url("tools/gerrit"),
+            "https://review.example.com/tools/gerrit"
+        );
+        assert_eq!(
+            provider(Some("jdoe"), true).clone_url("tools/gerrit"),
+            "https://review.example.com/a/tools/gerrit"
+        );
+    }
+}
@@ -1,21 +1,62 @@
 use crate::providers::{
-    create_exclude_regex_set, create_include_regex_set, Provider, APP_USER_AGENT,
+    create_exclude_regex_set, create_include_regex_set, url_host, ArchivedOptions, CloneUrlOptions,
+    FetchContext, HttpClient, HttpOptions, MetadataFilter, PathTemplate, Provider, TokenSource,
+    Visibility,
 };
 use crate::repository::Repository;
 use anyhow::Context;
 use console::style;
 use serde::{Deserialize, Serialize};
-use std::env;
 use std::fmt;
 
 #[derive(Deserialize, Debug)]
 struct GiteaRepository {
+    id: u64,
     full_name: String,
     clone_url: String,
     ssh_url: String,
     default_branch: String,
     archived: bool,
     fork: bool,
+    #[serde(default)]
+    private: bool,
+    #[serde(default)]
+    internal: bool,
+    #[serde(default)]
+    language: String,
+    #[serde(default)]
+    topics: Option<Vec<String>>,
+    // The repository this one was forked from, if it's a fork that we can see the parent of
+    #[serde(default)]
+    parent: Option<Box<GiteaRepository>>,
+}
+
+impl GiteaRepository {
+    fn visibility(&self) -> Visibility {
+        if self.private {
+            Visibility::Private
+        } else if self.internal {
+            Visibility::Internal
+        } else {
+            Visibility::Public
+        }
+    }
+}
+
+/// Which set of repositories `name` refers to
+#[derive(
+    Deserialize, Serialize, Debug, Clone, Copy, Eq, Ord, PartialEq, PartialOrd, clap::ValueEnum,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum GiteaKind {
+    /// Repositories owned by an organisation, including private and team repositories
+    Org,
+    /// Repositories owned by a user
+    User,
+    /// Every repository the token has access to
+    #[serde(rename = "self")]
+    #[value(name = "self")]
+    Authenticated,
 }
 
 fn default_env_var() -> String {
@@ -32,7 +73,9 @@ fn public_gitea_url() -> String {
 #[serde(rename_all = "lowercase")]
 #[command(about = "Add a Gitea user or organization by name")]
 pub struct GiteaProvider {
-    /// The name of the user or organisation to add
+    /// The name of the user or organisation to add. Not needed with `--kind=self`.
+    #[arg(default_value = "")]
+    #[serde(default, skip_serializing_if = "String::is_empty")]
     pub name: String,
 
     #[arg(long = "path", default_value = "gitea")]
@@ -68,10 +111,49 @@ pub struct GiteaProvider {
     #[serde(default = "public_gitea_url")]
     /// Gitea instance URL
     pub url: String,
+
+    #[arg(long = "kind", value_enum)]
+    #[serde(default)]
+    /// Whether the name is an organisation or a user. Use `self` to clone every repository the
+    /// token can access. Detected automatically if not given.
+    kind: Option<GiteaKind>,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    filter: MetadataFilter,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    archived: ArchivedOptions,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    template: PathTemplate,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    urls: CloneUrlOptions,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    token: TokenSource,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    pub http: HttpOptions,
 }
 
 impl fmt::Display for GiteaProvider {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.kind == Some(GiteaKind::Authenticated) {
+            return write!(
+                f,
+                "Gitea repositories at {} in directory {}, visible to the token stored in {}",
+                style(&self.url).green(),
+                style(&self.path).green(),
+                style(&self.env_var).green(),
+            );
+        }
         write!(
             f,
             "Gitea user/org {} at {} in directory {}, using the token stored in {}",
@@ -83,10 +165,61 @@ impl fmt::Display for GiteaProvider {
     }
 }
 
+impl GiteaProvider {
+    /// The user or organisation name, which isn't used when cloning everything the token can
+    /// access
+    pub fn owner_name(&self) -> &str {
+        if self.kind == Some(GiteaKind::Authenticated) {
+            ""
+        } else {
+            &self.name
+        }
+    }
+
+    /// Providers that clone everything their token can access are told apart by the token, for
+    /// the provider id
+    pub fn id_qualifiers(&self) -> Vec<(&'static str, String)> {
+        if self.kind != Some(GiteaKind::Authenticated) {
+            return vec![];
+        }
+        self.token
+            .id_qualifier(&self.env_var, &default_env_var())
+            .into_iter()
+            .collect()
+    }
+
+    fn detect_kind(
+        &self,
+        client: &HttpClient,
+        token: &str,
+        offline: bool,
+    ) -> anyhow::Result<GiteaKind> {
+        let url = format!("{}/api/v1/orgs/{}", self.url, self.name);
+        match client
+            .get(&url)
+            .set("Authorization", &format!("token {}", token))
+            .call()
+        {
+            Ok(_) => Ok(GiteaKind::Org),
+            Err(ureq::Error::Status(404, _)) => Ok(GiteaKind::User),
+            // Only successful responses are cached, so the org lookup of a user isn't
+            Err(_) if offline => Ok(GiteaKind::User),
+            Err(e) => Err(e).with_context(|| format!("Error looking up Gitea org {}", self.name)),
+        }
+    }
+
+    fn repositories_url(&self, kind: GiteaKind) -> String {
+        match kind {
+            GiteaKind::Org => format!("{}/api/v1/orgs/{}/repos", self.url, self.name),
+            GiteaKind::User => format!("{}/api/v1/users/{}/repos", self.url, self.name),
+            GiteaKind::Authenticated => format!("{}/api/v1/user/repos", self.url),
+        }
+    }
+}
+
 impl Provider for GiteaProvider {
     fn correctly_configured(&self) -> bool {
-        let token = env::var(&self.env_var);
-        if token.is_err() {
+        if !self.token.is_configured(&self.env_var) {
             println!(
                 "{}",
                 style(format!(
@@ -102,6 +235,13 @@ impl Provider for GiteaProvider {
             );
             return false;
         }
+        if self.name.is_empty() && self.kind != Some(GiteaKind::Authenticated) {
+            println!(
+                "{}",
+                style("Error: Either a user/org name or --kind=self must be given").red()
+            );
+            return false;
+        }
         if self.name.ends_with('/') {
             println!(
                 "{}",
@@ -113,28 +253,27 @@ impl Provider for GiteaProvider {
         true
     }
 
-    fn fetch_repositories(&self) -> anyhow::Result<Vec<Repository>> {
-        let gitea_token = env::var(&self.env_var)
-            .with_context(|| format!("Missing {} environment variable", self.env_var))?;
+    fn fetch_repositories(&self, context: &FetchContext) -> anyhow::Result<Vec<Repository>> {
+        let gitea_token = context.token(&self.token, &self.env_var, url_host(&self.url))?;
 
         let include_regex_set = create_include_regex_set(&self.include)?;
         let exclude_regex_set = create_exclude_regex_set(&self.exclude)?;
 
-        let agent = ureq::AgentBuilder::new()
-            .https_only(true)
-            .user_agent(APP_USER_AGENT)
-            .build();
+        let client = context.client(&self.http, &self.token, &self.env_var)?;
+
+        let kind = match self.kind {
+            Some(kind) => kind,
+            None => self.detect_kind(&client, &gitea_token, context.is_offline())?,
+        };
+        let repositories_url = self.repositories_url(kind);
 
         let mut page = 1;
         let mut repositories = Vec::new();
 
         loop {
-            let url = format!(
-                "{}/api/v1/users/{}/repos?page={}&limit=50",
-                self.url, self.name, page
-            );
+            let url = format!("{}?page={}&limit=50", repositories_url, page);
 
-            let response = agent
+            let response = client
                 .get(&url)
                 .set("Authorization", &format!("token {}", gitea_token))
                 .call()?;
@@ -147,21 +286,45 @@ impl Provider for GiteaProvider {
             repositories.extend(
                 repos
                     .into_iter()
-                    .filter(|r| !r.archived)
+                    .filter(|r| self.archived.is_match(r.archived))
                     .filter(|r| !self.skip_forks || !r.fork)
                     .filter(|r| include_regex_set.is_match(&r.full_name))
                     .filter(|r| !exclude_regex_set.is_match(&r.full_name))
+                    .filter(|r| {
+                        self.filter.is_match(
+                            r.topics.as_deref().unwrap_or_default(),
+                            Some(r.language.as_str()).filter(|l| !l.is_empty()),
+                            r.visibility(),
+                        )
+                    })
                     .map(|r| {
-                        Repository::new(
-                            format!("{}/{}", self.path, r.full_name),
+                        let upstream = r.parent.map(|parent| {
                             if self.auth_http {
-                                r.clone_url
+                                parent.clone_url
+                            } else {
+                                parent.ssh_url
+                            }
+                        });
+                        let url = self.urls.url(
+                            if self.auth_http {
+                                r.clone_url.clone()
                             } else {
                                 r.ssh_url
                             },
+                            &r.clone_url,
+                            &r.full_name,
+                        );
+                        Repository::new(
+                            self.archived.path(
+                                r.archived,
+                                self.template.path(&self.path, &r.clone_url, &r.full_name),
+                            ),
+                            url,
                             Some(r.default_branch),
-                            None,
+                            upstream.map(|u| self.urls.rewrite(u)),
                         )
+                        .with_archived(r.archived)
+                        .with_id(r.id.to_string())
                     }),
             );
 
@@ -171,3 +334,64 @@ impl Provider for GiteaProvider {
         Ok(repositories)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::test_server::{json_response, request_path, serve};
+    use crate::providers::ResponseCache;
+    use clap::Parser;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_fetch_user_repositories_offline() {
+        let (url, handle) = serve(vec![
+            "HTTP/1.1 404 Not Found".to_string(),
+            json_response(serde_json::json!([{
+                "id": 1,
+                "full_name": "alice/one",
+                "clone_url": "https://gitea.local/alice/one.git",
+                "ssh_url": "git@gitea.local:alice/one.git",
+                "default_branch": "main",
+                "archived": false,
+                "fork": false,
+            }])),
+            json_response(serde_json::json!([])),
+        ]);
+        let temp_dir = TempDir::new().unwrap();
+        let token_file = temp_dir.path().join("token");
+        std::fs::write(&token_file, "secret").unwrap();
+        let provider = GiteaProvider::parse_from([
+            "gitea",
+            "alice",
+            "--url",
+            &url,
+            "--token-file",
+            &token_file.display().to_string(),
+        ]);
+        let http = HttpOptions {
+            allow_http: true,
+            ..Default::default()
+        };
+
+        let context = FetchContext {
+            http: http.clone(),
+            cache: Some(ResponseCache::new(temp_dir.path(), false)),
+            ..Default::default()
+        };
+        let online = provider.fetch_repositories(&context).unwrap();
+        let requests = handle.join().unwrap();
+        assert_eq!(request_path(&requests[0]), "/api/v1/orgs/alice");
+        assert_eq!(online.len(), 1);
+
+        // Offline, the user is read from the cache alone, without the token or the server
+        std::fs::remove_file(&token_file).unwrap();
+        let context = FetchContext {
+            http,
+            cache: Some(ResponseCache::new(temp_dir.path(), true)),
+            ..Default::default()
+        };
+        let offline = provider.fetch_repositories(&context).unwrap();
+        assert_eq!(offline, online);
+    }
+}
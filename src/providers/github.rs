@@ -1,5 +1,7 @@
 use crate::providers::{
-    create_exclude_regex_set, create_include_regex_set, Provider, APP_USER_AGENT,
+    create_exclude_regex_set, create_include_regex_set, url_host, ArchivedOptions, CloneUrlOptions,
+    FetchContext, HttpClient, HttpOptions, MetadataFilter, PathTemplate, Provider, TokenSource,
+    Visibility,
 };
 use crate::repository::Repository;
 use anyhow::{bail, Context};
@@ -7,13 +9,14 @@ use console::style;
 use graphql_client::{GraphQLQuery, Response};
 use serde::{Deserialize, Serialize};
 use serde_json::json;
-use std::env;
 use std::fmt;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 // See https://github.com/graphql-rust/graphql-client/blob/master/graphql_client/tests/custom_scalars.rs#L6
 type GitSSHRemote = String;
 #[allow(clippy::upper_case_acronyms)]
 type URI = String;
+type DateTime = String;
 
 #[derive(GraphQLQuery)]
 #[graphql(
@@ -23,6 +26,167 @@ type URI = String;
 )]
 pub struct Repositories;
 
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "src/providers/graphql/github/schema.graphql",
+    query_path = "src/providers/graphql/github/projects.graphql",
+    response_derives = "Debug"
+)]
+pub struct ViewerRepositories;
+
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "src/providers/graphql/github/schema.graphql",
+    query_path = "src/providers/graphql/github/projects.graphql",
+    response_derives = "Debug"
+)]
+pub struct TeamRepositories;
+
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "src/providers/graphql/github/schema.graphql",
+    query_path = "src/providers/graphql/github/projects.graphql",
+    response_derives = "Debug"
+)]
+pub struct StarredRepositories;
+
+// Each query has its own copy of the RepositoryFields fragment, so we convert them all into
+// this to handle them in the same way.
+struct RepositoryNode {
+    id: String,
+    name_with_owner: String,
+    owner: String,
+    url: String,
+    ssh_url: String,
+    is_archived: bool,
+    is_fork: bool,
+    visibility: Visibility,
+    language: Option<String>,
+    topics: Vec<String>,
+    default_branch: Option<String>,
+    parent_ssh_url: Option<String>,
+}
+
+macro_rules! impl_from_repository_fields {
+    ($($module:ident),*) => {
+        $(
+            impl From<$module::RepositoryFields> for RepositoryNode {
+                fn from(repo: $module::RepositoryFields) -> Self {
+                    Self {
+                        id: repo.id,
+                        name_with_owner: repo.name_with_owner,
+                        owner: repo.owner.login,
+                        url: repo.url,
+                        ssh_url: repo.ssh_url,
+                        is_archived: repo.is_archived,
+                        is_fork: repo.is_fork,
+                        visibility: match repo.visibility {
+                            $module::RepositoryVisibility::PUBLIC => Visibility::Public,
+                            $module::RepositoryVisibility::INTERNAL => Visibility::Internal,
+                            _ => Visibility::Private,
+                        },
+                        language: repo.primary_language.map(|language| language.name),
+                        topics: repo
+                            .repository_topics
+                            .nodes
+                            .into_iter()
+                            .flatten()
+                            .flatten()
+                            .map(|node| node.topic.name)
+                            .collect(),
+                        default_branch: repo.default_branch_ref.map(|branch| branch.name),
+                        parent_ssh_url: repo.parent.map(|parent| parent.ssh_url),
+                    }
+                }
+            }
+        )*
+    };
+}
+
+impl_from_repository_fields!(
+    repositories,
+    viewer_repositories,
+    team_repositories,
+    starred_repositories
+);
+
+/// The parts of a response that are the same for every query. GitHub reports going over the
+/// rate limit with an error `type`, which `graphql_client`'s errors don't include.
+#[derive(Deserialize)]
+struct ResponseStatus {
+    data: Option<RateLimitData>,
+    #[serde(default)]
+    errors: Vec<ResponseError>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RateLimitData {
+    rate_limit: Option<RateLimit>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RateLimit {
+    remaining: u64,
+    reset_at: String,
+}
+
+#[derive(Deserialize)]
+struct ResponseError {
+    #[serde(rename = "type")]
+    error_type: Option<String>,
+}
+
+/// Parse a UTC `DateTime` like "2024-03-01T12:30:00Z"
+fn parse_date_time(value: &str) -> Option<SystemTime> {
+    let (date, time) = value.strip_suffix('Z')?.split_once('T')?;
+    let date: Vec<i64> = date
+        .split('-')
+        .map(|p| p.parse().ok())
+        .collect::<Option<_>>()?;
+    let time: Vec<i64> = time
+        .split(':')
+        .map(|p| p.parse().ok())
+        .collect::<Option<_>>()?;
+    let (&[year, month, day], &[hours, minutes, seconds]) = (&date[..], &time[..]) else {
+        return None;
+    };
+    // Days since the epoch, see https://howardhinnant.github.io/date_algorithms.html
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146097 + day_of_era - 719468;
+    let seconds = days * 86400 + hours * 3600 + minutes * 60 + seconds;
+    Some(UNIX_EPOCH + Duration::from_secs(u64::try_from(seconds).ok()?))
+}
+
+/// How the authenticated user is related to a repository
+#[derive(
+    Deserialize, Serialize, Debug, Clone, Copy, Eq, Ord, PartialEq, PartialOrd, clap::ValueEnum,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum GithubAffiliation {
+    /// Repositories owned by the user
+    Owner,
+    /// Repositories the user has been added to as a collaborator
+    Collaborator,
+    /// Repositories the user can access through an organisation they are a member of
+    OrganizationMember,
+}
+
+impl From<GithubAffiliation> for viewer_repositories::RepositoryAffiliation {
+    fn from(affiliation: GithubAffiliation) -> Self {
+        match affiliation {
+            GithubAffiliation::Owner => Self::OWNER,
+            GithubAffiliation::Collaborator => Self::COLLABORATOR,
+            GithubAffiliation::OrganizationMember => Self::ORGANIZATION_MEMBER,
+        }
+    }
+}
+
 fn default_env_var() -> String {
     String::from("GITHUB_TOKEN")
 }
@@ -37,7 +201,10 @@ fn public_github_url() -> String {
 #[serde(rename_all = "lowercase")]
 #[command(about = "Add a Github user or organization by name")]
 pub struct GithubProvider {
-    /// The name of the user or organisation to add.
+    /// The name of the user or organisation to add. Use `org/team-slug` to only add the
+    /// repositories a team has access to.
+    #[arg(required_unless_present = "viewer", default_value = "")]
+    #[serde(default, skip_serializing_if = "String::is_empty")]
     pub name: String,
     #[arg(long = "path", default_value = "github")]
     /// Clone repositories to a specific base path
@@ -74,49 +241,399 @@ pub struct GithubProvider {
     /// Github instance URL, if using Github Enterprise this should be
     /// http(s)://HOSTNAME/api/graphql
     pub url: String,
+
+    #[arg(long = "viewer")]
+    #[serde(default)]
+    /// Clone every repository the authenticated user has access to, across all users and
+    /// organisations, instead of the repositories owned by a single user or organisation
+    viewer: bool,
+
+    #[arg(long = "affiliation", value_enum, requires = "viewer")]
+    #[serde(default)]
+    /// Only clone repositories the authenticated user has one of these affiliations with.
+    /// Requires --viewer, and defaults to all affiliations.
+    affiliations: Vec<GithubAffiliation>,
+
+    #[arg(long = "owner", requires = "viewer")]
+    #[serde(default)]
+    /// Only clone repositories owned by these users or organisations. Requires --viewer.
+    owners: Vec<String>,
+
+    #[arg(long = "starred-path")]
+    #[serde(default)]
+    /// Also clone the repositories the user has starred into this path
+    starred_path: Option<String>,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    filter: MetadataFilter,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    archived: ArchivedOptions,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    template: PathTemplate,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    urls: CloneUrlOptions,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    token: TokenSource,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    pub http: HttpOptions,
 }
 
 impl fmt::Display for GithubProvider {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.viewer {
+            return write!(
+                f,
+                "Github repositories accessible with the token stored in {} in directory {}",
+                style(&self.env_var).green(),
+                style(&self.path).green(),
+            );
+        }
+        if self.name.contains('/') {
+            return write!(
+                f,
+                "Github team {} in directory {}, using the token stored in {}",
+                style(&self.name.to_lowercase()).green(),
+                style(&self.path).green(),
+                style(&self.env_var).green(),
+            );
+        }
         write!(
             f,
             "Github user/org {} in directory {}, using the token stored in {}",
             style(&self.name.to_lowercase()).green(),
-            style(&self.path.to_lowercase()).green(),
+            style(&self.path).green(),
             style(&self.env_var).green(),
         )
     }
 }
 
 impl GithubProvider {
-    fn parse_repo(
-        &self,
-        path: &str,
-        repo: &repositories::RepositoriesRepositoryOwnerRepositoriesNodes,
-    ) -> Repository {
-        let default_branch = repo
-            .default_branch_ref
-            .as_ref()
-            .map(|branch| branch.name.clone());
-        let upstream = repo.parent.as_ref().map(|parent| parent.ssh_url.clone());
-
-        Repository::new(
-            format!("{}/{}", path, repo.name_with_owner.clone()),
+    fn parse_repo(&self, path: &str, repo: RepositoryNode) -> Repository {
+        let url = self.urls.url(
             if self.auth_http {
                 repo.url.clone()
             } else {
-                repo.ssh_url.clone()
+                repo.ssh_url
             },
-            default_branch,
-            upstream,
+            &repo.url,
+            &repo.name_with_owner,
+        );
+        Repository::new(
+            self.archived.path(
+                repo.is_archived,
+                self.template.path(path, &repo.url, &repo.name_with_owner),
+            ),
+            url,
+            repo.default_branch,
+            repo.parent_ssh_url.map(|u| self.urls.rewrite(u)),
         )
+        .with_archived(repo.is_archived)
+        .with_id(repo.id)
+    }
+
+    /// `--viewer` providers are told apart by their token and filters, for the provider id
+    pub fn id_qualifiers(&self) -> Vec<(&'static str, String)> {
+        if !self.viewer {
+            return vec![];
+        }
+        let mut qualifiers: Vec<_> = self
+            .token
+            .id_qualifier(&self.env_var, &default_env_var())
+            .into_iter()
+            .collect();
+        if !self.affiliations.is_empty() {
+            let affiliations: Vec<String> = self
+                .affiliations
+                .iter()
+                .filter_map(clap::ValueEnum::to_possible_value)
+                .map(|a| a.get_name().to_string())
+                .collect();
+            qualifiers.push(("affiliation", affiliations.join(",")));
+        }
+        if !self.owners.is_empty() {
+            qualifiers.push(("owner", self.owners.join(",").to_lowercase()));
+        }
+        qualifiers
+    }
+
+    fn affiliations(&self) -> Vec<Option<viewer_repositories::RepositoryAffiliation>> {
+        let affiliations = if self.affiliations.is_empty() {
+            vec![
+                GithubAffiliation::Owner,
+                GithubAffiliation::Collaborator,
+                GithubAffiliation::OrganizationMember,
+            ]
+        } else {
+            self.affiliations.clone()
+        };
+        affiliations.into_iter().map(|a| Some(a.into())).collect()
+    }
+
+    /// Send a query, retrying any failed requests, and return the data from the response
+    fn run_query<Q: GraphQLQuery>(
+        client: &HttpClient,
+        url: &str,
+        auth_header: &str,
+        variables: Q::Variables,
+    ) -> anyhow::Result<Q::ResponseData> {
+        let q = Q::build_query(variables);
+        let mut attempt = 0;
+        let body = loop {
+            // The client retries transient errors and waits for rate limits to reset
+            let res = match client
+                .post(url)
+                .set("Authorization", auth_header)
+                .send_json(json!(&q))
+            {
+                Ok(res) => res,
+                Err(ureq::Error::Status(status, response)) => match response.into_string() {
+                    Ok(resp) => {
+                        bail!("Got status code {status}. Body: {resp}")
+                    }
+                    Err(e) => {
+                        bail!("Got status code {status}. Error reading body: {e}")
+                    }
+                },
+                Err(e) => return Err(e.into()),
+            };
+
+            let body = res.into_string()?;
+            let status: ResponseStatus = serde_json::from_str(&body)?;
+            if let Some(rate_limit) = status.data.and_then(|data| data.rate_limit) {
+                client.record_rate_limit(
+                    url,
+                    rate_limit.remaining,
+                    parse_date_time(&rate_limit.reset_at),
+                );
+            }
+            // Going over the rate limit isn't an HTTP error, so the client can't handle it
+            let rate_limited = status
+                .errors
+                .iter()
+                .any(|e| e.error_type.as_deref() == Some("RATE_LIMITED"));
+            if !rate_limited || !client.wait_for_rate_limit(url, attempt) {
+                break body;
+            }
+            attempt += 1;
+        };
+        let response_data: Response<Q::ResponseData> = serde_json::from_str(&body)?;
+
+        if let Some(errors) = response_data.errors {
+            let total_errors = errors.len();
+            let combined_errors: Vec<_> = errors
+                .into_iter()
+                .map(|e| {
+                    let mut message_str = e.message;
+                    if let Some(path) = e.path {
+                        let path_strings: Vec<String> =
+                            path.iter().map(|p| p.to_string()).collect();
+                        message_str.push_str(format!(" ({})", path_strings.join(".")).as_str());
+                    }
+                    message_str
+                })
+                .collect();
+            let combined_message = combined_errors.join("\n");
+            bail!(
+                "Received {} errors. Errors:\n{}",
+                total_errors,
+                combined_message
+            );
+        }
+
+        response_data
+            .data
+            .with_context(|| format!("Invalid response from GitHub: {}", body))
+    }
+
+    fn fetch_owner_repositories(
+        &self,
+        client: &HttpClient,
+        auth_header: &str,
+        include_forks: Option<bool>,
+    ) -> anyhow::Result<Vec<RepositoryNode>> {
+        let mut nodes = vec![];
+        let mut after = None;
+
+        loop {
+            let data = Self::run_query::<Repositories>(
+                client,
+                &self.url,
+                auth_header,
+                repositories::Variables {
+                    login: self.name.to_lowercase(),
+                    include_forks,
+                    after,
+                },
+            )?;
+            let response_repositories = data
+                .repository_owner
+                .with_context(|| format!("Github user/org {} could not be found", self.name))?
+                .repositories;
+
+            nodes.extend(
+                response_repositories
+                    .nodes
+                    .into_iter()
+                    .flatten()
+                    .flatten()
+                    .map(RepositoryNode::from),
+            );
+
+            if !response_repositories.page_info.has_next_page {
+                break;
+            }
+            after = response_repositories.page_info.end_cursor;
+        }
+
+        Ok(nodes)
+    }
+
+    fn fetch_team_repositories(
+        &self,
+        client: &HttpClient,
+        auth_header: &str,
+        org: &str,
+        team: &str,
+    ) -> anyhow::Result<Vec<RepositoryNode>> {
+        let mut nodes = vec![];
+        let mut after = None;
+
+        loop {
+            let data = Self::run_query::<TeamRepositories>(
+                client,
+                &self.url,
+                auth_header,
+                team_repositories::Variables {
+                    org: org.to_lowercase(),
+                    team: team.to_lowercase(),
+                    after,
+                },
+            )?;
+            let response_repositories = data
+                .organization
+                .with_context(|| format!("Github org {} could not be found", org))?
+                .team
+                .with_context(|| format!("Github team {} could not be found in {}", team, org))?
+                .repositories;
+
+            nodes.extend(
+                response_repositories
+                    .nodes
+                    .into_iter()
+                    .flatten()
+                    .flatten()
+                    .map(RepositoryNode::from),
+            );
+
+            if !response_repositories.page_info.has_next_page {
+                break;
+            }
+            after = response_repositories.page_info.end_cursor;
+        }
+
+        Ok(nodes)
+    }
+
+    fn fetch_starred_repositories(
+        &self,
+        client: &HttpClient,
+        auth_header: &str,
+    ) -> anyhow::Result<Vec<RepositoryNode>> {
+        let mut nodes = vec![];
+        let mut after = None;
+
+        loop {
+            let data = Self::run_query::<StarredRepositories>(
+                client,
+                &self.url,
+                auth_header,
+                starred_repositories::Variables {
+                    login: self.name.to_lowercase(),
+                    after,
+                },
+            )?;
+            let response_repositories = data
+                .user
+                .with_context(|| format!("Github user {} could not be found", self.name))?
+                .starred_repositories;
+
+            nodes.extend(
+                response_repositories
+                    .nodes
+                    .into_iter()
+                    .flatten()
+                    .flatten()
+                    .map(RepositoryNode::from),
+            );
+
+            if !response_repositories.page_info.has_next_page {
+                break;
+            }
+            after = response_repositories.page_info.end_cursor;
+        }
+
+        Ok(nodes)
+    }
+
+    fn fetch_viewer_repositories(
+        &self,
+        client: &HttpClient,
+        auth_header: &str,
+        include_forks: Option<bool>,
+    ) -> anyhow::Result<Vec<RepositoryNode>> {
+        let mut nodes = vec![];
+        let mut after = None;
+
+        loop {
+            let data = Self::run_query::<ViewerRepositories>(
+                client,
+                &self.url,
+                auth_header,
+                viewer_repositories::Variables {
+                    include_forks,
+                    after,
+                    affiliations: Some(self.affiliations()),
+                },
+            )?;
+            let response_repositories = data.viewer.repositories;
+
+            nodes.extend(
+                response_repositories
+                    .nodes
+                    .into_iter()
+                    .flatten()
+                    .flatten()
+                    .map(RepositoryNode::from)
+                    .filter(|r| {
+                        self.owners.is_empty()
+                            || self.owners.iter().any(|o| o.eq_ignore_ascii_case(&r.owner))
+                    }),
+            );
+
+            if !response_repositories.page_info.has_next_page {
+                break;
+            }
+            after = response_repositories.page_info.end_cursor;
+        }
+
+        Ok(nodes)
     }
 }
 
 impl Provider for GithubProvider {
     fn correctly_configured(&self) -> bool {
-        let token = env::var(&self.env_var);
-        if token.is_err() {
+        if !self.token.is_configured(&self.env_var) {
             println!(
                 "{}",
                 style(format!(
@@ -143,10 +660,24 @@ impl Provider for GithubProvider {
             );
             return false;
         }
-        if self.name.ends_with('/') {
+        if !self.viewer && self.name.is_empty() {
+            println!(
+                "{}",
+                style("Error: Either a user/org name or --viewer must be given").red()
+            );
+            return false;
+        }
+        if self.starred_path.is_some() && (self.viewer || self.name.contains('/')) {
+            println!(
+                "{}",
+                style("Error: Starred repositories can only be cloned for a user name").red()
+            );
+            return false;
+        }
+        if self.name.ends_with('/') || self.name.matches('/').count() > 1 {
             println!(
                 "{}",
-                style("Error: Ensure that names do not end in forward slashes").red()
+                style("Error: Ensure that names are a user/org or an org/team-slug").red()
             );
             println!("You specified: {}", self.name);
             return false;
@@ -154,9 +685,12 @@ impl Provider for GithubProvider {
         true
     }
 
-    fn fetch_repositories(&self) -> anyhow::Result<Vec<Repository>> {
-        let github_token = env::var(&self.env_var)
-            .with_context(|| format!("Missing {} environment variable", self.env_var))?;
+    fn fetch_repositories(&self, context: &FetchContext) -> anyhow::Result<Vec<Repository>> {
+        let github_token = context.token(
+            &self.token,
+            &self.env_var,
+            url_host(&self.url).trim_start_matches("api."),
+        )?;
 
         let auth_header = match github_token.as_str() {
             "none" => "none".to_string(),
@@ -165,10 +699,6 @@ impl Provider for GithubProvider {
             }
         };
 
-        let mut repositories = vec![];
-
-        let mut after = None;
-
         let include_regex_set = create_include_regex_set(&self.include)?;
         let exclude_regex_set = create_exclude_regex_set(&self.exclude)?;
 
@@ -176,108 +706,228 @@ impl Provider for GithubProvider {
         // states: false - no forks, true - only forks, none - all repositories.
         let include_forks: Option<bool> = if self.skip_forks { Some(false) } else { None };
 
-        let agent = ureq::AgentBuilder::new()
-            .https_only(true)
-            .user_agent(APP_USER_AGENT)
-            .build();
+        let client = context.client(&self.http, &self.token, &self.env_var)?;
 
-        loop {
-            let q = Repositories::build_query(repositories::Variables {
-                login: self.name.to_lowercase(),
-                include_forks,
-                after,
-            });
-            let res = {
-                let max_retries = 3;
-                let mut last_err = None;
-                let mut response = None;
-                for attempt in 0..max_retries {
-                    let result = agent
-                        .post(&self.url)
-                        .set("Authorization", &auth_header)
-                        .send_json(json!(&q));
-                    match result {
-                        Ok(resp) => {
-                            response = Some(resp);
-                            break;
-                        }
-                        Err(e) => {
-                            last_err = Some(e);
-                            if attempt < max_retries - 1 {
-                                std::thread::sleep(std::time::Duration::from_secs(1));
-                            }
-                        }
-                    }
-                }
-                match response {
-                    Some(resp) => resp,
-                    None => {
-                        let err = last_err.unwrap();
-                        match err {
-                            ureq::Error::Status(status, response) => match response.into_string() {
-                                Ok(resp) => {
-                                    bail!("Got status code {status}. Body: {resp}")
-                                }
-                                Err(e) => {
-                                    bail!("Got status code {status}. Error reading body: {e}")
-                                }
-                            },
-                            e => return Err(e.into()),
-                        }
-                    }
-                }
-            };
-
-            let body = res.into_string()?;
-            let response_data: Response<repositories::ResponseData> = serde_json::from_str(&body)?;
+        let nodes = if self.viewer {
+            self.fetch_viewer_repositories(&client, &auth_header, include_forks)?
+        } else if let Some((org, team)) = self.name.split_once('/') {
+            self.fetch_team_repositories(&client, &auth_header, org, team)?
+        } else {
+            self.fetch_owner_repositories(&client, &auth_header, include_forks)?
+        };
 
-            if let Some(errors) = response_data.errors {
-                let total_errors = errors.len();
-                let combined_errors: Vec<_> = errors
-                    .into_iter()
-                    .map(|e| {
-                        let mut message_str = e.message;
-                        if let Some(path) = e.path {
-                            let path_strings: Vec<String> =
-                                path.iter().map(|p| p.to_string()).collect();
-                            message_str.push_str(format!(" ({})", path_strings.join(".")).as_str());
-                        }
-                        message_str
-                    })
-                    .collect();
-                let combined_message = combined_errors.join("\n");
-                bail!(
-                    "Received {} errors. Errors:\n{}",
-                    total_errors,
-                    combined_message
-                );
-            }
+        let keep = |r: &RepositoryNode| {
+            self.archived.is_match(r.is_archived)
+                // Team and starred repositories can't be filtered by the API, so we need to
+                // check here too
+                && (!self.skip_forks || !r.is_fork)
+                && include_regex_set.is_match(&r.name_with_owner)
+                && !exclude_regex_set.is_match(&r.name_with_owner)
+                && self
+                    .filter
+                    .is_match(&r.topics, r.language.as_deref(), r.visibility)
+        };
 
-            let response_repositories = response_data
-                .data
-                .with_context(|| format!("Invalid response from GitHub: {}", body))?
-                .repository_owner
-                .with_context(|| format!("Invalid response from GitHub: {}", body))?
-                .repositories;
+        let mut repositories: Vec<Repository> = nodes
+            .into_iter()
+            .filter(keep)
+            .map(|repo| self.parse_repo(&self.path, repo))
+            .collect();
 
+        if let Some(starred_path) = &self.starred_path {
             repositories.extend(
-                response_repositories
-                    .nodes
-                    .unwrap()
-                    .iter()
-                    .map(|r| r.as_ref().unwrap())
-                    .filter(|r| !r.is_archived)
-                    .filter(|r| include_regex_set.is_match(&r.name_with_owner))
-                    .filter(|r| !exclude_regex_set.is_match(&r.name_with_owner))
-                    .map(|repo| self.parse_repo(&self.path, repo)),
+                self.fetch_starred_repositories(&client, &auth_header)?
+                    .into_iter()
+                    .filter(keep)
+                    .map(|repo| self.parse_repo(starred_path, repo)),
             );
-
-            if !response_repositories.page_info.has_next_page {
-                break;
-            }
-            after = response_repositories.page_info.end_cursor;
         }
 
         Ok(repositories)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::test_server::{json_response, request_json, serve};
+    use crate::providers::RateLimits;
+    use clap::Parser;
+
+    fn repository(name_with_owner: &str, parent: Option<&str>) -> serde_json::Value {
+        let (owner, _) = name_with_owner.split_once('/').unwrap();
+        json!({
+            "id": name_with_owner,
+            "nameWithOwner": name_with_owner,
+            "url": format!("https://github.com/{}", name_with_owner),
+            "sshUrl": format!("git@github.com:{}.git", name_with_owner),
+            "isArchived": false,
+            "isFork": parent.is_some(),
+            "visibility": "PUBLIC",
+            "primaryLanguage": null,
+            "repositoryTopics": {"nodes": []},
+            "owner": {"__typename": "User", "login": owner},
+            "defaultBranchRef": {"name": "main"},
+            "parent": parent.map(|parent| json!({
+                "url": format!("https://github.com/{}", parent),
+                "sshUrl": format!("git@github.com:{}.git", parent),
+            })),
+        })
+    }
+
+    /// The last page of a connection of repositories
+    fn repositories(nodes: Vec<serde_json::Value>) -> serde_json::Value {
+        json!({"pageInfo": {"hasNextPage": false, "endCursor": null}, "nodes": nodes})
+    }
+
+    fn fetch(args: &[&str], responses: Vec<String>) -> (Vec<Repository>, Vec<String>) {
+        let (url, handle) = serve(responses);
+        let provider = GithubProvider::parse_from(
+            ["github", "--url", &url, "--token-command", "echo secret"]
+                .iter()
+                .chain(args),
+        );
+        let context = FetchContext {
+            http: HttpOptions {
+                allow_http: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let repositories = provider.fetch_repositories(&context).unwrap();
+        (repositories, handle.join().unwrap())
+    }
+
+    fn names(repositories: &[Repository]) -> Vec<&str> {
+        repositories.iter().map(|r| r.name().as_str()).collect()
+    }
+
+    #[test]
+    fn test_parse_date_time() {
+        assert_eq!(parse_date_time("1970-01-01T00:00:00Z"), Some(UNIX_EPOCH));
+        assert_eq!(
+            parse_date_time("2000-02-29T23:59:59Z"),
+            Some(UNIX_EPOCH + Duration::from_secs(951868799))
+        );
+        assert_eq!(
+            parse_date_time("2024-03-01T00:00:00Z"),
+            Some(UNIX_EPOCH + Duration::from_secs(1709251200))
+        );
+        assert_eq!(parse_date_time("2024-03-01"), None);
+        assert_eq!(parse_date_time("2024-03-01T00:00:00+01:00"), None);
+    }
+
+    #[test]
+    fn test_run_query_waits_for_rate_limits() {
+        let (url, handle) = serve(vec![
+            // The limit has already reset, so the retry only waits a second
+            "HTTP/1.1 200 OK\r\nX-RateLimit-Reset: 0\r\n\r\n\
+                {\"data\": null, \"errors\": [{\"type\": \"RATE_LIMITED\", \"message\": \"API rate limit exceeded\"}]}"
+                .to_string(),
+            json_response(json!({"data": {
+                "rateLimit": {"remaining": 4999, "resetAt": "2024-03-01T00:00:00Z"},
+                "repositoryOwner": null,
+            }})),
+        ]);
+        let rate_limits = RateLimits::default();
+        let client = HttpClient::new(ureq::AgentBuilder::new().build(), &rate_limits)
+            .with_sleep(|delay| assert_eq!(delay, Duration::from_secs(1)));
+
+        let data = GithubProvider::run_query::<Repositories>(
+            &client,
+            &url,
+            "Bearer token",
+            repositories::Variables {
+                login: "orf".to_string(),
+                include_forks: None,
+                after: None,
+            },
+        )
+        .unwrap();
+        assert_eq!(handle.join().unwrap().len(), 2);
+        assert!(data.repository_owner.is_none());
+        assert_eq!(
+            rate_limits.summary(),
+            "API requests remaining: 127.0.0.1 4999"
+        );
+    }
+
+    #[test]
+    fn test_fetch_viewer_repositories() {
+        let (repositories, requests) = fetch(
+            &["--viewer", "--owner", "ORF"],
+            vec![json_response(json!({"data": {"viewer": {
+                "repositories": repositories(vec![
+                    repository("orf/one", None),
+                    repository("orf/fork", Some("other/fork")),
+                    repository("other/two", None),
+                ]),
+            }}}))],
+        );
+        assert_eq!(
+            request_json(&requests[0])["variables"],
+            json!({
+                "after": null,
+                "includeForks": null,
+                "affiliations": ["OWNER", "COLLABORATOR", "ORGANIZATION_MEMBER"],
+            })
+        );
+        // Owners are filtered here, as the API can't
+        assert_eq!(names(&repositories), ["github/orf/one", "github/orf/fork"]);
+        assert_eq!(
+            repositories[1].upstream.as_deref(),
+            Some("git@github.com:other/fork.git")
+        );
+    }
+
+    #[test]
+    fn test_fetch_team_repositories_without_forks() {
+        let (repositories, requests) = fetch(
+            &["My-Org/My-Team", "--skip-forks"],
+            vec![json_response(json!({"data": {"organization": {"team": {
+                "repositories": repositories(vec![
+                    repository("my-org/one", None),
+                    repository("my-org/fork", Some("other/fork")),
+                ]),
+            }}}}))],
+        );
+        assert_eq!(
+            request_json(&requests[0])["variables"],
+            json!({"org": "my-org", "team": "my-team", "after": null})
+        );
+        // The team query can't leave out forks, so they are filtered here
+        assert_eq!(names(&repositories), ["github/my-org/one"]);
+    }
+
+    #[test]
+    fn test_fetch_starred_repositories_without_forks() {
+        let (repositories, requests) = fetch(
+            &["orf", "--starred-path", "starred", "--skip-forks"],
+            vec![
+                json_response(json!({"data": {"repositoryOwner": {
+                    "__typename": "User",
+                    "repositories": repositories(vec![repository("orf/one", None)]),
+                }}})),
+                json_response(json!({"data": {"user": {
+                    "starredRepositories": repositories(vec![
+                        repository("other/two", None),
+                        repository("other/fork", Some("upstream/fork")),
+                    ]),
+                }}})),
+            ],
+        );
+        assert_eq!(
+            request_json(&requests[0])["variables"]["includeForks"],
+            false
+        );
+        assert_eq!(
+            request_json(&requests[1])["variables"],
+            json!({"login": "orf", "after": null})
+        );
+        // Starred repositories can't be filtered by the API either
+        assert_eq!(
+            names(&repositories),
+            ["github/orf/one", "starred/other/two"]
+        );
+    }
+}
@@ -1,5 +1,7 @@
 use crate::providers::{
-    create_exclude_regex_set, create_include_regex_set, Provider, APP_USER_AGENT,
+    create_exclude_regex_set, create_include_regex_set, url_host, ArchivedOptions, CloneUrlOptions,
+    FetchContext, HttpClient, HttpOptions, MetadataFilter, PathTemplate, Provider, TokenSource,
+    Visibility,
 };
 use crate::repository::Repository;
 use anyhow::{anyhow, Context};
@@ -7,53 +9,87 @@ use console::style;
 use graphql_client::{GraphQLQuery, Response};
 use serde::{Deserialize, Serialize};
 use serde_json::json;
-use std::env;
+use std::collections::HashMap;
 use std::fmt;
 
-// GraphQL queries we use to fetch user and group repositories.
-// Right now, annoyingly, Gitlab has a bug around GraphQL pagination:
-// https://gitlab.com/gitlab-org/gitlab/issues/33419
-// So, we don't paginate at all in these queries. I'll fix this once
-// the issue is closed.
-
+// The REST API doesn't return the languages of a project, so they're fetched with GraphQL when
+// repositories are filtered by their language.
 #[derive(GraphQLQuery)]
 #[graphql(
     schema_path = "src/providers/graphql/gitlab/schema.json",
     query_path = "src/providers/graphql/gitlab/projects.graphql",
     response_derives = "Debug"
 )]
-pub struct Repositories;
+pub struct ProjectLanguages;
+
+fn parse_visibility(visibility: Option<&str>) -> Visibility {
+    match visibility {
+        Some("public") => Visibility::Public,
+        Some("internal") => Visibility::Internal,
+        _ => Visibility::Private,
+    }
+}
 
-struct ProjectNode {
+/// GitLab doesn't have a primary language, so use the most common one. `languages` are the
+/// names of a project's languages and their share of the code.
+fn primary_language(languages: impl Iterator<Item = (String, Option<f64>)>) -> Option<String> {
+    languages
+        .max_by(|(_, a), (_, b)| a.unwrap_or(0.0).total_cmp(&b.unwrap_or(0.0)))
+        .map(|(name, _)| name)
+}
+
+// Unlike GraphQL, the REST API tells us which project a fork was created from, so projects are
+// listed with it.
+#[derive(Deserialize, Debug)]
+struct RestProject {
+    id: u64,
+    path_with_namespace: String,
+    ssh_url_to_repo: String,
+    http_url_to_repo: String,
+    // Missing for empty projects
+    default_branch: Option<String>,
+    #[serde(default)]
     archived: bool,
-    full_path: String,
-    ssh_url: String,
-    http_url: String,
-    root_ref: Option<String>,
+    visibility: Option<String>,
+    #[serde(default)]
+    topics: Vec<String>,
+    // Only present if the project is a fork and the parent is visible to us
+    forked_from_project: Option<RestForkedFromProject>,
 }
 
-impl From<repositories::RepositoriesGroupProjectsEdgesNode> for ProjectNode {
-    fn from(item: repositories::RepositoriesGroupProjectsEdgesNode) -> Self {
-        Self {
-            archived: item.archived.unwrap(),
-            root_ref: item.repository.and_then(|r| r.root_ref),
-            ssh_url: item.ssh_url_to_repo.expect("Unknown SSH URL"),
-            http_url: item.http_url_to_repo.expect("Unknown HTTP URL"),
-            full_path: item.full_path,
-        }
-    }
+#[derive(Deserialize, Debug)]
+struct RestForkedFromProject {
+    ssh_url_to_repo: String,
+    http_url_to_repo: String,
 }
 
-impl From<repositories::RepositoriesNamespaceProjectsEdgesNode> for ProjectNode {
-    fn from(item: repositories::RepositoriesNamespaceProjectsEdgesNode) -> Self {
-        Self {
-            archived: item.archived.unwrap(),
-            root_ref: item.repository.and_then(|r| r.root_ref),
-            ssh_url: item.ssh_url_to_repo.expect("Unknown SSH URL"),
-            http_url: item.http_url_to_repo.expect("Unknown HTTP URL"),
-            full_path: item.full_path,
-        }
-    }
+/// How the path of a project within the group is turned into a directory
+#[derive(
+    Deserialize,
+    Serialize,
+    Default,
+    Debug,
+    Clone,
+    Copy,
+    Eq,
+    Ord,
+    PartialEq,
+    PartialOrd,
+    clap::ValueEnum,
+)]
+#[serde(rename_all = "kebab-case")]
+pub enum GitlabLayout {
+    /// Use the full path of the project, including every group
+    #[default]
+    Full,
+    /// Leave out the group or user that was added, keeping any subgroups below it
+    StripRootGroup,
+    /// Put every project directly in the clone path, joining subgroups with the separator
+    Flat,
+}
+
+fn default_separator() -> String {
+    String::from("-")
 }
 
 static DEFAULT_GITLAB_URL: &str = "https://gitlab.com";
@@ -100,12 +136,46 @@ pub struct GitlabProvider {
     /// Don't clone repositories that match these regular expressions. The repository name
     /// includes the user or organisation name.
     exclude: Vec<String>,
-    // Currently does not work.
-    // https://gitlab.com/gitlab-org/gitlab/issues/121595
-    //    #[arg(long = "skip-forks")]
-    //    #[arg(about = "Don't clone forked repositories")]
-    //    #[serde(default = "default_forks")]
-    //    skip_forks: bool,
+
+    #[arg(long = "skip-forks")]
+    #[serde(default)]
+    /// Don't clone forked repositories. GitLab only says a project is a fork when the project
+    /// it was forked from is visible to you, so other forks are still cloned.
+    skip_forks: bool,
+
+    #[arg(long = "layout", value_enum, default_value = "full")]
+    #[serde(default)]
+    /// How to lay out projects in subgroups on disk
+    layout: GitlabLayout,
+
+    #[arg(long = "separator", default_value = "-")]
+    #[serde(default = "default_separator")]
+    /// The separator used to join subgroups with the flat layout
+    separator: String,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    filter: MetadataFilter,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    archived: ArchivedOptions,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    template: PathTemplate,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    urls: CloneUrlOptions,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    token: TokenSource,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    pub http: HttpOptions,
 }
 
 impl fmt::Display for GitlabProvider {
@@ -121,71 +191,115 @@ impl fmt::Display for GitlabProvider {
     }
 }
 
-impl Provider for GitlabProvider {
-    fn correctly_configured(&self) -> bool {
-        let token = env::var(&self.env_var);
-        if token.is_err() {
-            println!(
-                "{}",
-                style(format!(
-                    "Error: {} environment variable is not defined",
-                    self.env_var
-                ))
-                .red()
-            );
-            println!("Create a personal access token here:");
-            println!("{}/profile/personal_access_tokens", self.url);
-            println!(
-                "Set an environment variable called {} with the value",
-                self.env_var
-            );
-            return false;
-        }
-        if self.name.ends_with('/') {
-            println!(
-                "{}",
-                style("Error: Ensure that names do not end in forward slashes").red()
-            );
-            println!("You specified: {}", self.name);
-            return false;
+impl GitlabProvider {
+    /// The directory to clone a project into, relative to `path`
+    fn project_path(&self, full_path: &str) -> String {
+        let relative_path = || {
+            // Group paths are case insensitive
+            let prefix = format!("{}/", self.name.to_lowercase());
+            match full_path.get(..prefix.len()) {
+                Some(start) if start.to_lowercase() == prefix => &full_path[prefix.len()..],
+                _ => full_path,
+            }
+        };
+        match self.layout {
+            GitlabLayout::Full => full_path.to_string(),
+            GitlabLayout::StripRootGroup => relative_path().to_string(),
+            GitlabLayout::Flat => relative_path().replace('/', &self.separator),
         }
-        true
     }
-    fn fetch_repositories(&self) -> anyhow::Result<Vec<Repository>> {
-        let gitlab_token = env::var(&self.env_var)
-            .with_context(|| format!("Missing {} environment variable", self.env_var))?;
-        let mut repositories = vec![];
-        let mut after = Some("".to_string());
-        let name = self.name.to_string().to_lowercase();
 
-        let include_regex_set = create_include_regex_set(&self.include)?;
-        let exclude_regex_set = create_exclude_regex_set(&self.exclude)?;
+    /// List the projects in the group, including its subgroups, or in the user namespace
+    fn fetch_projects(
+        &self,
+        client: &HttpClient,
+        token: &str,
+        offline: bool,
+    ) -> anyhow::Result<Vec<RestProject>> {
+        let name = self.name.to_lowercase().replace('/', "%2F");
+        let group_url = format!(
+            "{}/api/v4/groups/{}/projects?include_subgroups=true&per_page=100",
+            self.url, name
+        );
+        if let Some(projects) = self.fetch_project_pages(client, token, &group_url, offline)? {
+            return Ok(projects);
+        }
+        let user_url = format!("{}/api/v4/users/{}/projects?per_page=100", self.url, name);
+        self.fetch_project_pages(client, token, &user_url, false)?
+            .ok_or_else(|| {
+                anyhow!(
+                    "Gitlab group/user {} could not be found. Are you sure you have access?",
+                    self.name.to_lowercase()
+                )
+            })
+    }
 
-        let agent = ureq::AgentBuilder::new()
-            .https_only(true)
-            .user_agent(APP_USER_AGENT)
-            .build();
+    /// Fetch every page of a REST listing of projects, or None if the listing doesn't exist
+    fn fetch_project_pages(
+        &self,
+        client: &HttpClient,
+        token: &str,
+        url: &str,
+        offline: bool,
+    ) -> anyhow::Result<Option<Vec<RestProject>>> {
+        let mut projects = vec![];
+        let mut page = Some("1".to_string());
+        while let Some(current_page) = page {
+            let res = match client
+                .get(&format!("{}&page={}", url, current_page))
+                .set("Authorization", format!("Bearer {}", token).as_str())
+                .call()
+            {
+                Ok(res) => res,
+                Err(ureq::Error::Status(404, _)) if current_page == "1" => return Ok(None),
+                // Only successful responses are cached, so the group listing of a user isn't
+                Err(_) if offline && current_page == "1" => return Ok(None),
+                Err(e) => return Err(e.into()),
+            };
+            // This is empty on the last page
+            page = res
+                .header("x-next-page")
+                .filter(|p| !p.is_empty())
+                .map(String::from);
+            let page_projects: Vec<RestProject> = res
+                .into_json()
+                .with_context(|| format!("Invalid response from Gitlab for {}", url))?;
+            projects.extend(page_projects);
+        }
+        Ok(Some(projects))
+    }
 
-        loop {
-            let q = Repositories::build_query(repositories::Variables {
+    /// Fetch the primary language of every project in the group or user namespace, keyed by
+    /// the full path of the project.
+    fn fetch_languages(
+        &self,
+        client: &HttpClient,
+        token: &str,
+    ) -> anyhow::Result<HashMap<String, String>> {
+        let name = self.name.to_lowercase();
+        let mut languages = HashMap::new();
+        let mut after = Some("".to_string());
+        while after.is_some() {
+            let q = ProjectLanguages::build_query(project_languages::Variables {
                 name: name.clone(),
                 after,
             });
-            let res = agent
+            let res = client
                 .post(format!("{}/api/graphql", self.url).as_str())
-                .set("Authorization", format!("Bearer {}", gitlab_token).as_str())
+                .set("Authorization", format!("Bearer {}", token).as_str())
                 .set("Content-Type", "application/json")
                 .send_json(json!(&q))?;
             let json = res.into_json()?;
 
-            let response_body: Response<repositories::ResponseData> = serde_json::from_value(json)?;
+            let response_body: Response<project_languages::ResponseData> =
+                serde_json::from_value(json)?;
             let data = response_body.data.expect("Missing data");
 
-            let temp_repositories: Vec<ProjectNode>;
+            let page: Vec<(String, Option<String>)>;
             // This is annoying but I'm still not sure how to unify it.
             if let Some(group) = data.group {
                 let group_data = group.projects;
-                temp_repositories = group_data
+                page = group_data
                     .edges
                     .expect("missing edges")
                     .into_iter()
@@ -193,12 +307,17 @@ impl Provider for GitlabProvider {
                     .flatten()
                     // Extract the node, which is also Some(T)
                     .filter_map(|x| x.node)
-                    .map(ProjectNode::from)
+                    .map(|node| {
+                        let languages = node.languages.unwrap_or_default();
+                        let language =
+                            primary_language(languages.into_iter().map(|l| (l.name, l.share)));
+                        (node.full_path, language)
+                    })
                     .collect();
                 after = group_data.page_info.end_cursor;
             } else if let Some(namespace) = data.namespace {
                 let namespace_data = namespace.projects;
-                temp_repositories = namespace_data
+                page = namespace_data
                     .edges
                     .expect("missing edges")
                     .into_iter()
@@ -206,7 +325,12 @@ impl Provider for GitlabProvider {
                     .flatten()
                     // Extract the node, which is also Some(T)
                     .filter_map(|x| x.node)
-                    .map(ProjectNode::from)
+                    .map(|node| {
+                        let languages = node.languages.unwrap_or_default();
+                        let language =
+                            primary_language(languages.into_iter().map(|l| (l.name, l.share)));
+                        (node.full_path, language)
+                    })
                     .collect();
                 after = namespace_data.page_info.end_cursor;
             } else {
@@ -216,30 +340,259 @@ impl Provider for GitlabProvider {
                 ));
             }
 
-            repositories.extend(
-                temp_repositories
-                    .into_iter()
-                    .filter(|r| !r.archived)
-                    .filter(|r| include_regex_set.is_match(&r.full_path))
-                    .filter(|r| !exclude_regex_set.is_match(&r.full_path))
-                    .map(|r| {
-                        Repository::new(
-                            format!("{}/{}", self.path, r.full_path),
-                            if self.auth_http {
-                                r.http_url
-                            } else {
-                                r.ssh_url
-                            },
-                            r.root_ref,
-                            None,
-                        )
-                    }),
+            languages.extend(
+                page.into_iter()
+                    .filter_map(|(full_path, language)| Some((full_path, language?))),
             );
+        }
+        Ok(languages)
+    }
+}
 
-            if after.is_none() {
-                break;
-            }
+impl Provider for GitlabProvider {
+    fn correctly_configured(&self) -> bool {
+        if !self.token.is_configured(&self.env_var) {
+            println!(
+                "{}",
+                style(format!(
+                    "Error: {} environment variable is not defined",
+                    self.env_var
+                ))
+                .red()
+            );
+            println!("Create a personal access token here:");
+            println!("{}/profile/personal_access_tokens", self.url);
+            println!(
+                "Set an environment variable called {} with the value",
+                self.env_var
+            );
+            return false;
         }
-        Ok(repositories)
+        if self.name.ends_with('/') {
+            println!(
+                "{}",
+                style("Error: Ensure that names do not end in forward slashes").red()
+            );
+            println!("You specified: {}", self.name);
+            return false;
+        }
+        true
+    }
+    fn fetch_repositories(&self, context: &FetchContext) -> anyhow::Result<Vec<Repository>> {
+        let gitlab_token = context.token(&self.token, &self.env_var, url_host(&self.url))?;
+
+        let include_regex_set = create_include_regex_set(&self.include)?;
+        let exclude_regex_set = create_exclude_regex_set(&self.exclude)?;
+
+        let client = context.client(&self.http, &self.token, &self.env_var)?;
+
+        let projects = self.fetch_projects(&client, &gitlab_token, context.is_offline())?;
+        let languages = if self.filter.filters_languages() {
+            self.fetch_languages(&client, &gitlab_token)?
+        } else {
+            HashMap::new()
+        };
+
+        Ok(projects
+            .into_iter()
+            .filter(|r| self.archived.is_match(r.archived))
+            .filter(|r| include_regex_set.is_match(&r.path_with_namespace))
+            .filter(|r| !exclude_regex_set.is_match(&r.path_with_namespace))
+            .filter(|r| {
+                self.filter.is_match(
+                    &r.topics,
+                    languages.get(&r.path_with_namespace).map(String::as_str),
+                    parse_visibility(r.visibility.as_deref()),
+                )
+            })
+            .filter(|r| !self.skip_forks || r.forked_from_project.is_none())
+            .map(|r| {
+                let upstream = r.forked_from_project.map(|parent| {
+                    if self.auth_http {
+                        parent.http_url_to_repo
+                    } else {
+                        parent.ssh_url_to_repo
+                    }
+                });
+                let url = self.urls.url(
+                    if self.auth_http {
+                        r.http_url_to_repo.clone()
+                    } else {
+                        r.ssh_url_to_repo
+                    },
+                    &r.http_url_to_repo,
+                    &r.path_with_namespace,
+                );
+                Repository::new(
+                    self.archived.path(
+                        r.archived,
+                        format!(
+                            "{}/{}",
+                            self.path,
+                            self.template
+                                .expand(&r.http_url_to_repo, &r.path_with_namespace)
+                                .unwrap_or_else(|| self.project_path(&r.path_with_namespace))
+                        ),
+                    ),
+                    url,
+                    r.default_branch,
+                    upstream.map(|u| self.urls.rewrite(u)),
+                )
+                .with_archived(r.archived)
+                // The same global id GraphQL uses, which earlier versions recorded
+                .with_id(format!("gid://gitlab/Project/{}", r.id))
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::test_server::{json_response, request_path, serve};
+    use clap::Parser;
+
+    fn provider(name: &str, layout: GitlabLayout) -> GitlabProvider {
+        GitlabProvider {
+            name: name.to_string(),
+            layout,
+            separator: default_separator(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_primary_language() {
+        let languages = vec![
+            ("Shell".to_string(), Some(10.5)),
+            ("Rust".to_string(), Some(80.0)),
+            ("Makefile".to_string(), None),
+        ];
+        assert_eq!(
+            primary_language(languages.into_iter()),
+            Some("Rust".to_string())
+        );
+        assert_eq!(primary_language(std::iter::empty()), None);
+    }
+
+    #[test]
+    fn test_project_path() {
+        let full_path = "company-root/engineering/team/service";
+        assert_eq!(
+            provider("company-root", GitlabLayout::Full).project_path(full_path),
+            full_path
+        );
+        assert_eq!(
+            provider("Company-Root/Engineering", GitlabLayout::StripRootGroup)
+                .project_path(full_path),
+            "team/service"
+        );
+        assert_eq!(
+            provider("company-root/engineering", GitlabLayout::Flat).project_path(full_path),
+            "team-service"
+        );
+        // Projects outside of the group keep their full path
+        assert_eq!(
+            provider("other-root", GitlabLayout::StripRootGroup).project_path(full_path),
+            full_path
+        );
+    }
+
+    fn project(id: u64, path: &str, parent: Option<&str>) -> serde_json::Value {
+        let mut project = json!({
+            "id": id,
+            "path_with_namespace": path,
+            "ssh_url_to_repo": format!("git@gitlab.local:{}.git", path),
+            "http_url_to_repo": format!("https://gitlab.local/{}.git", path),
+            "default_branch": "main",
+            "archived": false,
+            "visibility": "private",
+            "topics": [],
+        });
+        if let Some(parent) = parent {
+            project["forked_from_project"] = json!({
+                "ssh_url_to_repo": format!("git@gitlab.local:{}.git", parent),
+                "http_url_to_repo": format!("https://gitlab.local/{}.git", parent),
+            });
+        }
+        project
+    }
+
+    fn fetch(args: &[&str], responses: Vec<String>) -> (Vec<Repository>, Vec<String>) {
+        let (url, handle) = serve(responses);
+        let provider = GitlabProvider::parse_from(
+            ["gitlab", "--url", &url, "--token-command", "echo secret"]
+                .iter()
+                .chain(args),
+        );
+        let context = FetchContext {
+            http: HttpOptions {
+                allow_http: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let repositories = provider.fetch_repositories(&context).unwrap();
+        (repositories, handle.join().unwrap())
+    }
+
+    #[test]
+    fn test_fetch_user_projects() {
+        let (repositories, requests) = fetch(
+            &["alice"],
+            vec![
+                "HTTP/1.1 404 Not Found".to_string(),
+                json_response(json!([project(1, "alice/one", None)]))
+                    .replace("\r\n\r\n", "\r\nx-next-page: 2\r\n\r\n"),
+                json_response(json!([project(2, "alice/fork", Some("bob/fork"))])),
+            ],
+        );
+        assert_eq!(
+            requests.iter().map(|r| request_path(r)).collect::<Vec<_>>(),
+            [
+                "/api/v4/groups/alice/projects?include_subgroups=true&per_page=100&page=1",
+                "/api/v4/users/alice/projects?per_page=100&page=1",
+                "/api/v4/users/alice/projects?per_page=100&page=2",
+            ]
+        );
+        assert_eq!(repositories.len(), 2);
+        assert_eq!(repositories[0].name(), "gitlab/alice/one");
+        assert_eq!(repositories[0].upstream, None);
+        assert_eq!(repositories[0].id(), Some("gid://gitlab/Project/1"));
+        assert_eq!(
+            repositories[1].upstream.as_deref(),
+            Some("git@gitlab.local:bob/fork.git")
+        );
+    }
+
+    #[test]
+    fn test_fetch_group_projects_by_language_without_forks() {
+        let languages = |path: &str, language: &str| {
+            let languages = json!([{"name": language, "share": 90.0}]);
+            json!({"node": {"fullPath": path, "languages": languages}})
+        };
+        let (repositories, requests) = fetch(
+            &["my-group", "--skip-forks", "--language", "rust"],
+            vec![
+                json_response(json!([
+                    project(1, "my-group/one", None),
+                    project(2, "my-group/two", None),
+                    project(3, "my-group/fork", Some("other/fork")),
+                ])),
+                json_response(json!({"data": {"namespace": null, "group": {"projects": {
+                    "edges": [
+                        languages("my-group/one", "Rust"),
+                        languages("my-group/two", "Python"),
+                        languages("my-group/fork", "Rust"),
+                    ],
+                    "pageInfo": {"hasNextPage": false, "endCursor": null},
+                }}}})),
+            ],
+        );
+        assert_eq!(request_path(&requests[1]), "/api/graphql");
+        assert_eq!(
+            repositories.iter().map(|r| r.name()).collect::<Vec<_>>(),
+            ["gitlab/my-group/one"]
+        );
     }
 }
@@ -0,0 +1,100 @@
+use crate::providers::{FetchContext, Provider};
+use crate::repository::Repository;
+use console::style;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+#[derive(Deserialize, Serialize, Debug, Clone, Eq, Ord, PartialEq, PartialOrd, clap::Args)]
+pub struct ManualRepository {
+    /// The path to clone the repository to, relative to the workspace
+    path: String,
+
+    /// The URL to clone the repository from
+    url: String,
+
+    #[arg(long = "branch")]
+    #[serde(default)]
+    /// The primary branch of the repository
+    branch: Option<String>,
+
+    #[arg(long = "upstream")]
+    #[serde(default)]
+    /// The URL to set as the `upstream` remote
+    upstream: Option<String>,
+}
+
+/// Repositories that are listed directly in the configuration, for hosts that don't have an API
+/// we can use to discover them.
+#[derive(Deserialize, Serialize, Default, Debug, Eq, Ord, PartialEq, PartialOrd)]
+#[serde(rename_all = "lowercase")]
+pub struct ManualProvider {
+    #[serde(rename = "repo", default)]
+    repos: Vec<ManualRepository>,
+}
+
+// `git workspace add manual` adds a single repository, so we can't derive these: clap has no
+// way to build a list of structs from arguments.
+impl clap::FromArgMatches for ManualProvider {
+    fn from_arg_matches(matches: &clap::ArgMatches) -> Result<Self, clap::Error> {
+        Ok(ManualProvider {
+            repos: vec![ManualRepository::from_arg_matches(matches)?],
+        })
+    }
+
+    fn update_from_arg_matches(&mut self, matches: &clap::ArgMatches) -> Result<(), clap::Error> {
+        *self = Self::from_arg_matches(matches)?;
+        Ok(())
+    }
+}
+
+impl clap::Args for ManualProvider {
+    fn augment_args(cmd: clap::Command) -> clap::Command {
+        ManualRepository::augment_args(cmd).about("Add a repository by its clone URL")
+    }
+
+    fn augment_args_for_update(cmd: clap::Command) -> clap::Command {
+        ManualRepository::augment_args_for_update(cmd).about("Add a repository by its clone URL")
+    }
+}
+
+impl fmt::Display for ManualProvider {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.repos.as_slice() {
+            [repo] => write!(
+                f,
+                "Repository {} in directory {}",
+                style(&repo.url).green(),
+                style(&repo.path).green(),
+            ),
+            repos => write!(
+                f,
+                "{} manually configured repositories",
+                style(repos.len()).green()
+            ),
+        }
+    }
+}
+
+impl Provider for ManualProvider {
+    fn correctly_configured(&self) -> bool {
+        for repo in &self.repos {
+            if repo.path.is_empty() || repo.url.is_empty() {
+                println!(
+                    "{}",
+                    style("Error: Ensure that every repository has a path and a URL").red()
+                );
+                return false;
+            }
+        }
+        true
+    }
+
+    fn fetch_repositories(&self, _context: &FetchContext) -> anyhow::Result<Vec<Repository>> {
+        Ok(self
+            .repos
+            .iter()
+            .cloned()
+            .map(|r| Repository::new(r.path, r.url, r.branch, r.upstream))
+            .collect())
+    }
+}
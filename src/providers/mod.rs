@@ -1,20 +1,81 @@
+mod azure_devops;
+mod bitbucket;
+mod bitbucket_server;
+mod cache;
+mod gerrit;
 mod gitea;
 mod github;
 mod gitlab;
+mod http;
+mod manual;
+mod srht;
+#[cfg(test)]
+mod test_server;
+mod token;
 
 use crate::repository::Repository;
 use anyhow::Context;
+pub use azure_devops::AzureDevOpsProvider;
+pub use bitbucket::BitbucketProvider;
+pub use bitbucket_server::BitbucketServerProvider;
+pub use cache::ResponseCache;
+pub use gerrit::GerritProvider;
 pub use gitea::GiteaProvider;
 pub use github::GithubProvider;
 pub use gitlab::GitlabProvider;
+pub use http::{HttpClient, HttpOptions, HttpRequest, RateLimits};
+pub use manual::ManualProvider;
+use serde::{Deserialize, Serialize};
+pub use srht::SrhtProvider;
 use std::fmt;
+pub use token::TokenSource;
 
 pub static APP_USER_AGENT: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"),);
 
 pub trait Provider: fmt::Display {
     /// Returns true if the provider should work, otherwise prints an error and return false
     fn correctly_configured(&self) -> bool;
-    fn fetch_repositories(&self) -> anyhow::Result<Vec<Repository>>;
+    fn fetch_repositories(&self, context: &FetchContext) -> anyhow::Result<Vec<Repository>>;
+}
+
+/// State shared by every provider while fetching repositories
+#[derive(Default, Debug)]
+pub struct FetchContext {
+    /// HTTP options from the workspace configuration, used unless a provider overrides them
+    pub http: HttpOptions,
+    /// The rate limits of every API we've talked to
+    pub rate_limits: RateLimits,
+    /// Where to cache API responses. Nothing is cached without one.
+    pub cache: Option<ResponseCache>,
+}
+
+impl FetchContext {
+    pub fn is_offline(&self) -> bool {
+        self.cache.as_ref().is_some_and(ResponseCache::is_offline)
+    }
+
+    /// Read a provider's token. Offline no requests are sent, so a placeholder is used instead
+    /// of running token commands or asking for credentials.
+    pub fn token(&self, token: &TokenSource, env_var: &str, host: &str) -> anyhow::Result<String> {
+        if self.is_offline() {
+            return Ok("offline".to_string());
+        }
+        token.resolve(env_var, host)
+    }
+
+    /// Build an HTTP client for a provider with its own HTTP options. Responses are cached per
+    /// token source, as providers with different tokens can see different repositories.
+    pub fn client(
+        &self,
+        http: &HttpOptions,
+        token: &TokenSource,
+        env_var: &str,
+    ) -> anyhow::Result<HttpClient<'_>> {
+        Ok(
+            HttpClient::new(http.merge(&self.http).agent()?, &self.rate_limits)
+                .with_cache(self.cache.as_ref(), token.describe(env_var)),
+        )
+    }
 }
 
 pub fn create_exclude_regex_set(items: &Vec<String>) -> anyhow::Result<regex::RegexSet> {
@@ -33,3 +94,380 @@ pub fn create_include_regex_set(items: &Vec<String>) -> anyhow::Result<regex::Re
         Ok(regex::RegexSet::new(items).context("Error parsing include regular expressions")?)
     }
 }
+
+#[derive(
+    Deserialize, Serialize, Debug, Clone, Copy, Eq, Ord, PartialEq, PartialOrd, clap::ValueEnum,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Visibility {
+    /// Visible to everyone
+    Public,
+    /// Only visible to people that have been given access
+    Private,
+    /// Visible to every member of an organisation or instance
+    Internal,
+}
+
+/// Filters on repository metadata that are common to providers that expose it
+#[derive(Deserialize, Serialize, Default, Debug, Eq, Ord, PartialEq, PartialOrd, clap::Args)]
+pub struct MetadataFilter {
+    #[arg(long = "topic")]
+    #[serde(default)]
+    /// Only clone repositories that have at least one of these topics
+    topics: Vec<String>,
+
+    #[arg(long = "language")]
+    #[serde(default)]
+    /// Only clone repositories where the primary language is one of these
+    languages: Vec<String>,
+
+    #[arg(long = "visibility", value_enum)]
+    #[serde(default)]
+    /// Only clone repositories with this visibility
+    visibility: Option<Visibility>,
+}
+
+impl MetadataFilter {
+    /// Whether repositories are filtered by their primary language
+    pub fn filters_languages(&self) -> bool {
+        !self.languages.is_empty()
+    }
+
+    pub fn is_match(
+        &self,
+        topics: &[String],
+        language: Option<&str>,
+        visibility: Visibility,
+    ) -> bool {
+        let topic_matches = self.topics.is_empty()
+            || self
+                .topics
+                .iter()
+                .any(|t| topics.iter().any(|topic| topic.eq_ignore_ascii_case(t)));
+        let language_matches = self.languages.is_empty()
+            || language.is_some_and(|language| {
+                self.languages
+                    .iter()
+                    .any(|l| l.eq_ignore_ascii_case(language))
+            });
+        let visibility_matches = self.visibility.map_or(true, |v| v == visibility);
+        topic_matches && language_matches && visibility_matches
+    }
+}
+
+#[derive(
+    Deserialize,
+    Serialize,
+    Default,
+    Debug,
+    Clone,
+    Copy,
+    Eq,
+    Ord,
+    PartialEq,
+    PartialOrd,
+    clap::ValueEnum,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum ArchivedMode {
+    /// Don't clone archived repositories
+    #[default]
+    Skip,
+    /// Clone archived repositories along with all other repositories
+    Include,
+    /// Only clone archived repositories
+    Only,
+}
+
+/// How providers that know about archived repositories should treat them
+#[derive(Deserialize, Serialize, Default, Debug, Eq, Ord, PartialEq, PartialOrd, clap::Args)]
+pub struct ArchivedOptions {
+    #[arg(long = "archived", value_enum, default_value = "skip")]
+    #[serde(default)]
+    /// Whether to clone archived repositories
+    archived: ArchivedMode,
+
+    #[arg(long = "archived-path")]
+    #[serde(default)]
+    /// Clone archived repositories under this path, relative to the workspace
+    archived_path: Option<String>,
+}
+
+impl ArchivedOptions {
+    pub fn is_match(&self, is_archived: bool) -> bool {
+        match self.archived {
+            ArchivedMode::Skip => !is_archived,
+            ArchivedMode::Include => true,
+            ArchivedMode::Only => is_archived,
+        }
+    }
+
+    /// Prefix the path of archived repositories with the archived path, if one is set
+    pub fn path(&self, is_archived: bool, path: String) -> String {
+        match &self.archived_path {
+            Some(archived_path) if is_archived => format!("{}/{}", archived_path, path),
+            _ => path,
+        }
+    }
+}
+
+/// Lay out repositories on disk using a template rather than the owner and name
+#[derive(Deserialize, Serialize, Default, Debug, Eq, Ord, PartialEq, PartialOrd, clap::Args)]
+pub struct PathTemplate {
+    #[arg(long = "path-template")]
+    #[serde(default)]
+    /// Clone repositories to this path relative to `path`, e.g. "{host}/{owner}/{name}".
+    /// {full_name} is the owner and name separated by a slash.
+    path_template: Option<String>,
+}
+
+impl PathTemplate {
+    /// Expand the template for a repository, if one is set. `url` is used for the host, and
+    /// `full_name` is split on the last slash into the owner and name.
+    pub fn expand(&self, url: &str, full_name: &str) -> Option<String> {
+        let template = self.path_template.as_ref()?;
+        Some(expand_template(template, url, full_name))
+    }
+
+    /// The path to clone a repository to, defaulting to `path/full_name`
+    pub fn path(&self, path: &str, url: &str, full_name: &str) -> String {
+        let relative_path = self.expand(url, full_name);
+        format!("{}/{}", path, relative_path.as_deref().unwrap_or(full_name))
+    }
+}
+
+/// Replace `{host}`, `{owner}`, `{name}` and `{full_name}` in a template
+fn expand_template(template: &str, url: &str, full_name: &str) -> String {
+    let (owner, name) = full_name.rsplit_once('/').unwrap_or(("", full_name));
+    template
+        .replace("{host}", url_host(url))
+        .replace("{owner}", owner)
+        .replace("{name}", name)
+        .replace("{full_name}", full_name)
+}
+
+/// Replace the start of a URL, like git's `url.<base>.insteadOf`
+#[derive(Deserialize, Serialize, Debug, Clone, Eq, Ord, PartialEq, PartialOrd)]
+pub struct UrlRewrite {
+    from: String,
+    to: String,
+}
+
+fn parse_url_rewrite(value: &str) -> Result<UrlRewrite, String> {
+    match value.split_once('=') {
+        Some((from, to)) if !from.is_empty() => Ok(UrlRewrite {
+            from: from.to_string(),
+            to: to.to_string(),
+        }),
+        _ => Err(format!("Expected FROM=TO, got {}", value)),
+    }
+}
+
+/// Change the URLs repositories are cloned from before they are written to the lockfile
+#[derive(Deserialize, Serialize, Default, Debug, Eq, Ord, PartialEq, PartialOrd, clap::Args)]
+pub struct CloneUrlOptions {
+    #[arg(long = "clone-url-template")]
+    #[serde(default)]
+    /// Clone repositories from this URL instead, e.g. "git@github-work:{owner}/{name}.git".
+    /// {host}, {owner}, {name} and {full_name} are replaced like in --path-template.
+    clone_url_template: Option<String>,
+
+    #[arg(long = "rewrite", value_parser = parse_url_rewrite)]
+    #[serde(default)]
+    /// Replace the start of clone URLs, in the form FROM=TO. This also applies to the
+    /// upstream URL of forks. When several rules match, the longest FROM is used.
+    rewrite: Vec<UrlRewrite>,
+}
+
+impl CloneUrlOptions {
+    /// The URL to clone a repository from. `http_url` and `full_name` are used to expand the
+    /// template.
+    pub fn url(&self, url: String, http_url: &str, full_name: &str) -> String {
+        let url = match &self.clone_url_template {
+            Some(template) => expand_template(template, http_url, full_name),
+            None => url,
+        };
+        self.rewrite(url)
+    }
+
+    /// Apply the rewrite rules to a URL, for example the upstream of a fork
+    pub fn rewrite(&self, url: String) -> String {
+        self.rewrite
+            .iter()
+            .filter(|rule| url.starts_with(&rule.from))
+            .max_by_key(|rule| rule.from.len())
+            .map_or_else(
+                || url.clone(),
+                |rule| format!("{}{}", rule.to, &url[rule.from.len()..]),
+            )
+    }
+}
+
+/// Get the host name out of an HTTP URL, without any credentials or port
+pub(crate) fn url_host(url: &str) -> &str {
+    let without_scheme = url.split_once("://").map_or(url, |(_, rest)| rest);
+    let authority = without_scheme.split('/').next().unwrap_or(without_scheme);
+    let host = authority
+        .rsplit_once('@')
+        .map_or(authority, |(_, host)| host);
+    host.split(':').next().unwrap_or(host)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_offline_token() {
+        let token = TokenSource::default();
+        let env_var = "GIT_WORKSPACE_TEST_MISSING_TOKEN";
+        assert!(FetchContext::default()
+            .token(&token, env_var, "github.com")
+            .is_err());
+
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let context = FetchContext {
+            cache: Some(ResponseCache::new(temp_dir.path(), true)),
+            ..Default::default()
+        };
+        assert!(context.is_offline());
+        assert_eq!(
+            context.token(&token, env_var, "github.com").unwrap(),
+            "offline"
+        );
+    }
+
+    #[test]
+    fn test_metadata_filter() {
+        let topics = vec!["team-payments".to_string(), "rust".to_string()];
+
+        assert!(MetadataFilter::default().is_match(&[], None, Visibility::Private));
+
+        let filter = MetadataFilter {
+            topics: vec!["Team-Payments".to_string(), "team-search".to_string()],
+            ..Default::default()
+        };
+        assert!(filter.is_match(&topics, None, Visibility::Public));
+        assert!(!filter.is_match(&["rust".to_string()], None, Visibility::Public));
+
+        let filter = MetadataFilter {
+            languages: vec!["rust".to_string()],
+            visibility: Some(Visibility::Internal),
+            ..Default::default()
+        };
+        assert!(filter.is_match(&[], Some("Rust"), Visibility::Internal));
+        assert!(!filter.is_match(&[], Some("Rust"), Visibility::Public));
+        assert!(!filter.is_match(&[], Some("Python"), Visibility::Internal));
+        assert!(!filter.is_match(&[], None, Visibility::Internal));
+    }
+
+    #[test]
+    fn test_archived_options() {
+        let options = ArchivedOptions::default();
+        assert!(options.is_match(false));
+        assert!(!options.is_match(true));
+        assert_eq!(options.path(true, "github/a/b".to_string()), "github/a/b");
+
+        let options = ArchivedOptions {
+            archived: ArchivedMode::Only,
+            archived_path: Some("archived".to_string()),
+        };
+        assert!(!options.is_match(false));
+        assert!(options.is_match(true));
+        assert_eq!(
+            options.path(true, "github/a/b".to_string()),
+            "archived/github/a/b"
+        );
+        assert_eq!(options.path(false, "github/a/b".to_string()), "github/a/b");
+    }
+
+    #[test]
+    fn test_path_template() {
+        let template = PathTemplate::default();
+        assert_eq!(
+            template.path(
+                "github",
+                "https://github.com/orf/git-workspace",
+                "orf/git-workspace"
+            ),
+            "github/orf/git-workspace"
+        );
+
+        let template = PathTemplate {
+            path_template: Some("{host}/{owner}/{name}".to_string()),
+        };
+        assert_eq!(
+            template.path(
+                ".",
+                "https://github.com/orf/git-workspace",
+                "orf/git-workspace"
+            ),
+            "./github.com/orf/git-workspace"
+        );
+        assert_eq!(
+            template.path(
+                "src",
+                "https://jdoe@git.company.com:8443/group/sub/repo.git",
+                "group/sub/repo"
+            ),
+            "src/git.company.com/group/sub/repo"
+        );
+
+        let template = PathTemplate {
+            path_template: Some("{owner}-{name}".to_string()),
+        };
+        assert_eq!(
+            template.expand("https://gitea.com", "org/repo").unwrap(),
+            "org-repo"
+        );
+    }
+
+    #[test]
+    fn test_clone_url_options() {
+        let options = CloneUrlOptions::default();
+        assert_eq!(
+            options.url(
+                "git@github.com:orf/git-workspace.git".to_string(),
+                "https://github.com/orf/git-workspace",
+                "orf/git-workspace"
+            ),
+            "git@github.com:orf/git-workspace.git"
+        );
+
+        let options = CloneUrlOptions {
+            clone_url_template: None,
+            rewrite: vec![
+                parse_url_rewrite("git@github.com:=github-work:").unwrap(),
+                parse_url_rewrite("git@github.com:orf/=github-personal:orf/").unwrap(),
+            ],
+        };
+        assert_eq!(
+            options.rewrite("git@github.com:orf/git-workspace.git".to_string()),
+            "github-personal:orf/git-workspace.git"
+        );
+        assert_eq!(
+            options.rewrite("git@github.com:django/django.git".to_string()),
+            "github-work:django/django.git"
+        );
+        assert_eq!(
+            options.rewrite("https://gitlab.com/a/b.git".to_string()),
+            "https://gitlab.com/a/b.git"
+        );
+
+        let options = CloneUrlOptions {
+            clone_url_template: Some("ssh://git@ssh.{host}:443/{owner}/{name}.git".to_string()),
+            rewrite: vec![],
+        };
+        assert_eq!(
+            options.url(
+                "git@github.com:orf/git-workspace.git".to_string(),
+                "https://github.com/orf/git-workspace",
+                "orf/git-workspace"
+            ),
+            "ssh://git@ssh.github.com:443/orf/git-workspace.git"
+        );
+
+        assert!(parse_url_rewrite("no-equals").is_err());
+        assert!(parse_url_rewrite("=to").is_err());
+    }
+}
@@ -0,0 +1,287 @@
+use crate::providers::{
+    create_exclude_regex_set, create_include_regex_set, url_host, CloneUrlOptions, FetchContext,
+    HttpOptions, PathTemplate, Provider, TokenSource,
+};
+use crate::repository::Repository;
+use anyhow::{bail, Context};
+use console::style;
+use graphql_client::{GraphQLQuery, Response};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::fmt;
+
+// See https://github.com/graphql-rust/graphql-client/blob/master/graphql_client/tests/custom_scalars.rs#L6
+type Cursor = String;
+
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "src/providers/graphql/srht/schema.graphql",
+    query_path = "src/providers/graphql/srht/projects.graphql",
+    response_derives = "Debug"
+)]
+pub struct Repositories;
+
+fn default_env_var() -> String {
+    String::from("SRHT_TOKEN")
+}
+
+static DEFAULT_SRHT_URL: &str = "https://git.sr.ht";
+
+fn public_srht_url() -> String {
+    DEFAULT_SRHT_URL.to_string()
+}
+
+#[derive(Deserialize, Serialize, Default, Debug, Eq, Ord, PartialEq, PartialOrd, clap::Parser)]
+#[serde(rename_all = "lowercase")]
+#[command(about = "Add a SourceHut user by name")]
+pub struct SrhtProvider {
+    /// The name of the user to add, with or without the leading tilde
+    pub name: String,
+
+    #[arg(long = "path", default_value = "sourcehut")]
+    /// Clone repos to a specific path
+    path: String,
+
+    #[arg(long = "env-name", short = 'e', default_value = "SRHT_TOKEN")]
+    #[serde(default = "default_env_var")]
+    /// Environment variable containing the personal access token
+    env_var: String,
+
+    #[arg(long = "include")]
+    #[serde(default)]
+    /// Only clone repositories that match these regular expressions. The repository name
+    /// includes the user name.
+    include: Vec<String>,
+
+    #[arg(long = "auth-http")]
+    #[serde(default)]
+    /// Use HTTP authentication instead of SSH
+    auth_http: bool,
+
+    #[arg(long = "exclude")]
+    #[serde(default)]
+    /// Don't clone repositories that match these regular expressions. The repository name
+    /// includes the user name.
+    exclude: Vec<String>,
+
+    #[arg(long = "url", default_value = DEFAULT_SRHT_URL)]
+    #[serde(default = "public_srht_url")]
+    /// git.sr.ht instance URL
+    pub url: String,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    template: PathTemplate,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    urls: CloneUrlOptions,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    token: TokenSource,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    pub http: HttpOptions,
+}
+
+impl fmt::Display for SrhtProvider {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "SourceHut user {} at {} in directory {}, using the token stored in {}",
+            style(&self.username()).green(),
+            style(&self.url).green(),
+            style(&self.path).green(),
+            style(&self.env_var).green(),
+        )
+    }
+}
+
+impl SrhtProvider {
+    fn username(&self) -> &str {
+        self.name.trim_start_matches('~')
+    }
+
+    fn clone_url(&self, repo_name: &str) -> String {
+        let url = self.url.trim_end_matches('/');
+        if self.auth_http {
+            format!("{}/~{}/{}", url, self.username(), repo_name)
+        } else {
+            let host = url.split("://").last().unwrap_or(url);
+            format!("git@{}:~{}/{}", host, self.username(), repo_name)
+        }
+    }
+}
+
+impl Provider for SrhtProvider {
+    fn correctly_configured(&self) -> bool {
+        if !self.token.is_configured(&self.env_var) {
+            println!(
+                "{}",
+                style(format!(
+                    "Error: {} environment variable is not defined",
+                    self.env_var
+                ))
+                .red()
+            );
+            println!(
+                "Create a personal access token with read access to git.sr.ht here: {}",
+                style("https://meta.sr.ht/oauth2").green()
+            );
+            println!(
+                "Then set a {} environment variable with the value",
+                style(&self.env_var).green()
+            );
+            return false;
+        }
+        if self.name.contains('/') {
+            println!(
+                "{}",
+                style("Error: Ensure that user names do not contain forward slashes").red()
+            );
+            println!("You specified: {}", self.name);
+            return false;
+        }
+        true
+    }
+
+    fn fetch_repositories(&self, context: &FetchContext) -> anyhow::Result<Vec<Repository>> {
+        let srht_token = context.token(&self.token, &self.env_var, url_host(&self.url))?;
+
+        let include_regex_set = create_include_regex_set(&self.include)?;
+        let exclude_regex_set = create_exclude_regex_set(&self.exclude)?;
+
+        let client = context.client(&self.http, &self.token, &self.env_var)?;
+
+        let mut repositories = vec![];
+        let mut cursor = None;
+
+        loop {
+            let q = Repositories::build_query(repositories::Variables {
+                username: self.username().to_string(),
+                cursor,
+            });
+            let body = client
+                .post(&format!("{}/query", self.url.trim_end_matches('/')))
+                .set("Authorization", &format!("Bearer {}", srht_token))
+                .send_json(json!(&q))?
+                .into_string()?;
+            let response_data: Response<repositories::ResponseData> = serde_json::from_str(&body)?;
+
+            if let Some(errors) = response_data.errors {
+                let messages: Vec<_> = errors.into_iter().map(|e| e.message).collect();
+                bail!(
+                    "Received {} errors. Errors:\n{}",
+                    messages.len(),
+                    messages.join("\n")
+                );
+            }
+
+            let user = response_data
+                .data
+                .with_context(|| format!("Invalid response from SourceHut: {}", body))?
+                .user
+                .with_context(|| format!("SourceHut user {} could not be found", self.name))?;
+
+            for repo in user.repositories.results {
+                let full_name = format!("{}/{}", self.username(), repo.name);
+                if !include_regex_set.is_match(&full_name) || exclude_regex_set.is_match(&full_name)
+                {
+                    continue;
+                }
+                repositories.push(
+                    Repository::new(
+                        self.template.path(&self.path, &self.url, &full_name),
+                        self.urls
+                            .url(self.clone_url(&repo.name), &self.url, &full_name),
+                        repo.head
+                            .map(|head| head.name.trim_start_matches("refs/heads/").to_string()),
+                        None,
+                    )
+                    .with_id(repo.id.to_string()),
+                );
+            }
+
+            cursor = user.repositories.cursor;
+            if cursor.is_none() {
+                break;
+            }
+        }
+
+        Ok(repositories)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::test_server::{json_response, request_json, request_path, serve};
+    use clap::Parser;
+
+    fn page(names: &[&str], cursor: Option<&str>) -> String {
+        let results: Vec<_> = names
+            .iter()
+            .enumerate()
+            .map(|(id, name)| json!({"id": id, "name": name, "HEAD": {"name": "refs/heads/main"}}))
+            .collect();
+        json_response(json!({"data": {"user": {
+            "canonicalName": "~alice",
+            "repositories": {"cursor": cursor, "results": results},
+        }}}))
+    }
+
+    /// Returns the URL of the server along with the repositories and the requests
+    fn fetch(args: &[&str], responses: Vec<String>) -> (String, Vec<Repository>, Vec<String>) {
+        let (url, handle) = serve(responses);
+        let provider = SrhtProvider::parse_from(
+            ["sourcehut", "--url", &url, "--token-command", "echo secret"]
+                .iter()
+                .chain(args),
+        );
+        let context = FetchContext {
+            http: HttpOptions {
+                allow_http: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let repositories = provider.fetch_repositories(&context).unwrap();
+        (url, repositories, handle.join().unwrap())
+    }
+
+    #[test]
+    fn test_fetch_repositories_follows_cursor() {
+        let (url, repositories, requests) = fetch(
+            &["~alice"],
+            vec![page(&["one"], Some("next")), page(&["two"], None)],
+        );
+        assert_eq!(request_path(&requests[0]), "/query");
+        // The tilde is left out of the username
+        let variables = |request: &str| request_json(request)["variables"].clone();
+        assert_eq!(
+            variables(&requests[0]),
+            json!({"username": "alice", "cursor": null})
+        );
+        assert_eq!(
+            variables(&requests[1]),
+            json!({"username": "alice", "cursor": "next"})
+        );
+        assert_eq!(
+            repositories.iter().map(|r| r.name()).collect::<Vec<_>>(),
+            ["sourcehut/alice/one", "sourcehut/alice/two"]
+        );
+        assert_eq!(repositories[0].branch.as_deref(), Some("main"));
+        let host = url.trim_start_matches("http://");
+        assert_eq!(repositories[0].url(), &format!("git@{}:~alice/one", host));
+    }
+
+    #[test]
+    fn test_fetch_repositories_over_https() {
+        let (url, repositories, requests) =
+            fetch(&["alice", "--auth-http"], vec![page(&["one"], None)]);
+        assert_eq!(request_json(&requests[0])["variables"]["username"], "alice");
+        assert_eq!(repositories[0].url(), &format!("{}/~alice/one", url));
+    }
+}
@@ -0,0 +1,65 @@
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::thread;
+
+/// Serve each response in order, one per connection, and return each request.
+/// Responses are a status line and headers, optionally followed by a blank line and a body.
+/// The Content-Length and Connection headers are added, and `{url}` is replaced with the URL of
+/// the server, for responses that link to other pages.
+pub fn serve(responses: Vec<String>) -> (String, thread::JoinHandle<Vec<String>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let server_url = url.clone();
+    let handle = thread::spawn(move || {
+        let mut requests = vec![];
+        for response in responses {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut request = String::new();
+            // Read up to the blank line after the headers, then the body
+            while reader.read_line(&mut request).unwrap() > 2 {}
+            let length = request
+                .lines()
+                .filter_map(|line| line.split_once(": "))
+                .find(|(name, _)| name.eq_ignore_ascii_case("content-length"))
+                .map_or(0, |(_, value)| value.parse().unwrap());
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body).unwrap();
+            request.push_str(&String::from_utf8(body).unwrap());
+            requests.push(request);
+            let response = response.replace("{url}", &server_url);
+            let (head, body) = response
+                .split_once("\r\n\r\n")
+                .unwrap_or((response.as_str(), ""));
+            write!(
+                stream,
+                "{}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                head,
+                body.len(),
+                body
+            )
+            .unwrap();
+        }
+        requests
+    });
+    (url, handle)
+}
+
+/// A successful response with a JSON body
+pub fn json_response(body: serde_json::Value) -> String {
+    format!(
+        "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\n\r\n{}",
+        body
+    )
+}
+
+/// The path and query string of a request returned by `serve`
+pub fn request_path(request: &str) -> &str {
+    request.split(' ').nth(1).unwrap_or_default()
+}
+
+/// The JSON body of a request returned by `serve`
+pub fn request_json(request: &str) -> serde_json::Value {
+    let (_, body) = request.split_once("\r\n\r\n").unwrap();
+    serde_json::from_str(body).unwrap()
+}
@@ -0,0 +1,198 @@
+use anyhow::{anyhow, bail, Context};
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+/// Where to read the token used to talk to a provider from. Without any of these the token is
+/// read from the provider's environment variable.
+#[derive(Deserialize, Serialize, Default, Debug, Eq, Ord, PartialEq, PartialOrd, clap::Args)]
+pub struct TokenSource {
+    #[arg(long = "token-file")]
+    #[serde(default)]
+    /// Read the token from this file
+    token_file: Option<String>,
+
+    #[arg(long = "token-command")]
+    #[serde(default)]
+    /// Run this command with the shell and use its output as the token, e.g. "gh auth token"
+    token_command: Option<String>,
+
+    #[arg(long = "git-credential")]
+    #[serde(default)]
+    /// Ask `git credential fill` for the password of the provider's host
+    git_credential: bool,
+}
+
+impl TokenSource {
+    /// Whether a token can be found, without running any commands
+    pub fn is_configured(&self, env_var: &str) -> bool {
+        self.token_file.is_some()
+            || self.token_command.is_some()
+            || self.git_credential
+            || env::var(env_var).is_ok()
+    }
+
+    /// Read the token. `host` is the host name passed to `git credential fill`.
+    pub fn resolve(&self, env_var: &str, host: &str) -> anyhow::Result<String> {
+        if let Some(path) = &self.token_file {
+            let token = fs::read_to_string(path)
+                .with_context(|| format!("Cannot read token file {}", path))?;
+            return non_empty(token.trim(), || format!("Token file {} is empty", path));
+        }
+        if let Some(command) = &self.token_command {
+            let output = shell_command(command)
+                .stderr(Stdio::inherit())
+                .output()
+                .with_context(|| format!("Error running token command `{}`", command))?;
+            if !output.status.success() {
+                bail!("Token command `{}` failed: {}", command, output.status);
+            }
+            let token = String::from_utf8(output.stdout)
+                .with_context(|| format!("Token command `{}` printed invalid UTF-8", command))?;
+            return non_empty(token.trim(), || {
+                format!("Token command `{}` didn't print a token", command)
+            });
+        }
+        if self.git_credential {
+            return git_credential_fill(host);
+        }
+        env::var(env_var).with_context(|| format!("Missing {} environment variable", env_var))
+    }
+
+    /// Where the token is read from, like `env:GITHUB_TOKEN`, without reading it. This keeps
+    /// cached responses and provider ids apart per token, so it has to work offline too.
+    pub fn describe(&self, env_var: &str) -> String {
+        if let Some(path) = &self.token_file {
+            return format!("file:{}", path);
+        }
+        if let Some(command) = &self.token_command {
+            return format!("command:{}", command);
+        }
+        if self.git_credential {
+            // The host is part of every request's URL and provider id
+            return "git-credential".to_string();
+        }
+        format!("env:{}", env_var)
+    }
+
+    /// Where the token is read from, for provider ids that need to tell tokens apart. Nothing is
+    /// added for the provider's default environment variable, to keep the ids short.
+    pub fn id_qualifier(
+        &self,
+        env_var: &str,
+        default_env_var: &str,
+    ) -> Option<(&'static str, String)> {
+        let token = self.describe(env_var);
+        (token != format!("env:{}", default_env_var)).then_some(("token", token))
+    }
+}
+
+fn non_empty(token: &str, message: impl FnOnce() -> String) -> anyhow::Result<String> {
+    if token.is_empty() {
+        bail!(message());
+    }
+    Ok(token.to_string())
+}
+
+#[cfg(unix)]
+fn shell_command(command: &str) -> Command {
+    let mut shell = Command::new("sh");
+    shell.arg("-c").arg(command);
+    shell
+}
+
+#[cfg(windows)]
+fn shell_command(command: &str) -> Command {
+    let mut shell = Command::new("cmd");
+    shell.arg("/C").arg(command);
+    shell
+}
+
+/// Ask git's configured credential helpers for the password of an HTTPS host
+fn git_credential_fill(host: &str) -> anyhow::Result<String> {
+    let mut child = Command::new("git")
+        .args(["credential", "fill"])
+        // Fail rather than prompting if no helper has a password for the host
+        .env("GIT_TERMINAL_PROMPT", "0")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .with_context(|| "Error running git credential fill")?;
+    child
+        .stdin
+        .take()
+        .expect("stdin is piped")
+        .write_all(format!("protocol=https\nhost={}\n\n", host).as_bytes())
+        .with_context(|| "Error writing to git credential fill")?;
+    let output = child
+        .wait_with_output()
+        .with_context(|| "Error running git credential fill")?;
+    if !output.status.success() {
+        bail!("git credential fill found no credentials for {}", host);
+    }
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .find_map(|line| line.strip_prefix("password="))
+        .map(String::from)
+        .ok_or_else(|| anyhow!("git credential fill returned no password for {}", host))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_token_file() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("token");
+        fs::write(&path, "secret\n").unwrap();
+
+        let source = TokenSource {
+            token_file: Some(path.display().to_string()),
+            ..Default::default()
+        };
+        assert!(source.is_configured("GIT_WORKSPACE_TEST_UNSET_TOKEN"));
+        assert_eq!(
+            source
+                .resolve("GIT_WORKSPACE_TEST_UNSET_TOKEN", "github.com")
+                .unwrap(),
+            "secret"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_token_command() {
+        let source = TokenSource {
+            token_command: Some("echo secret".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            source
+                .resolve("GIT_WORKSPACE_TEST_UNSET_TOKEN", "github.com")
+                .unwrap(),
+            "secret"
+        );
+
+        let source = TokenSource {
+            token_command: Some("false".to_string()),
+            ..Default::default()
+        };
+        assert!(source
+            .resolve("GIT_WORKSPACE_TEST_UNSET_TOKEN", "github.com")
+            .is_err());
+    }
+
+    #[test]
+    fn test_missing_environment_variable() {
+        let source = TokenSource::default();
+        assert!(!source.is_configured("GIT_WORKSPACE_TEST_UNSET_TOKEN"));
+        assert!(source
+            .resolve("GIT_WORKSPACE_TEST_UNSET_TOKEN", "github.com")
+            .is_err());
+    }
+}
@@ -1,9 +1,12 @@
+use crate::config::PathCase;
+use crate::providers::HttpOptions;
 use anyhow::{anyhow, Context};
 use console::{strip_ansi_codes, truncate_str};
 use git2::build::CheckoutBuilder;
 use git2::{Repository as Git2Repository, StatusOptions};
 use indicatif::ProgressBar;
 use serde::{Deserialize, Serialize};
+use std::fs;
 use std::io::{BufRead, BufReader};
 use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
@@ -15,6 +18,16 @@ pub struct Repository {
     url: String,
     pub upstream: Option<String>,
     pub branch: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    commit_msg_hook: Option<String>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    archived: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    provider: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    renamed_from: Option<String>,
 }
 
 impl Repository {
@@ -38,9 +51,142 @@ impl Repository {
             url,
             branch,
             upstream,
+            commit_msg_hook: None,
+            archived: false,
+            provider: None,
+            id: None,
+            renamed_from: None,
         }
     }
 
+    /// Mark the repository as archived on the provider, which means it is read-only
+    pub fn with_archived(mut self, archived: bool) -> Repository {
+        self.archived = archived;
+        self
+    }
+
+    pub fn is_archived(&self) -> bool {
+        self.archived
+    }
+
+    /// Record the id of the provider the repository was fetched from
+    pub fn with_provider(mut self, provider: String) -> Repository {
+        self.provider = Some(provider);
+        self
+    }
+
+    /// The id of the provider the repository was fetched from. Lockfiles written before this
+    /// was recorded don't have one.
+    pub fn provider(&self) -> Option<&str> {
+        self.provider.as_deref()
+    }
+
+    /// Record the provider's id for the repository, which stays the same when it is renamed
+    pub fn with_id(mut self, id: String) -> Repository {
+        self.id = Some(id);
+        self
+    }
+
+    pub fn id(&self) -> Option<&str> {
+        self.id.as_deref()
+    }
+
+    /// Record the path of an existing checkout of the repository, from before it was renamed
+    pub fn with_renamed_from(mut self, renamed_from: Option<String>) -> Repository {
+        self.renamed_from = renamed_from;
+        self
+    }
+
+    pub fn renamed_from(&self) -> Option<&String> {
+        self.renamed_from.as_ref()
+    }
+
+    /// Move the checkout from before the repository was renamed to its new path, and point its
+    /// origin at the new URL. Returns false if there is nothing to move.
+    pub fn move_renamed(&self, root: &Path) -> anyhow::Result<bool> {
+        let renamed_from = match &self.renamed_from {
+            Some(renamed_from) => root.join(renamed_from),
+            None => return Ok(false),
+        };
+        let path = root.join(self.name());
+        if path.exists() || !renamed_from.join(".git").is_dir() {
+            return Ok(false);
+        }
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("Error creating directory {}", parent.display()))?;
+        }
+        fs::rename(&renamed_from, &path).with_context(|| {
+            format!(
+                "Error moving {} to {}",
+                renamed_from.display(),
+                path.display()
+            )
+        })?;
+
+        let output = Command::new("git")
+            .arg("-C")
+            .arg(&path)
+            .arg("remote")
+            .arg("set-url")
+            .arg("origin")
+            .arg(&self.url)
+            .output()?;
+        if !output.status.success() {
+            let stderr =
+                std::str::from_utf8(&output.stderr).with_context(|| "Error decoding git output")?;
+            return Err(anyhow!(
+                "Failed to set the origin of {} to {}: {}",
+                path.display(),
+                self.url,
+                stderr.trim()
+            ));
+        }
+        Ok(true)
+    }
+
+    /// Download a `commit-msg` hook from the given URL into the repository after it is cloned
+    pub fn with_commit_msg_hook(mut self, url: String) -> Repository {
+        self.commit_msg_hook = Some(url);
+        self
+    }
+
+    pub fn has_commit_msg_hook(&self) -> bool {
+        self.commit_msg_hook.is_some()
+    }
+
+    /// Download the `commit-msg` hook, connecting with the provider's HTTP options
+    pub fn install_commit_msg_hook(&self, root: &Path, http: &HttpOptions) -> anyhow::Result<()> {
+        let url = match &self.commit_msg_hook {
+            Some(url) => url,
+            None => return Ok(()),
+        };
+
+        let hook = http
+            .agent()?
+            .get(url)
+            .call()
+            .with_context(|| format!("Error downloading commit-msg hook from {}", url))?
+            .into_string()?;
+
+        let repo = Git2Repository::open(root.join(self.name()))?;
+        let hooks_dir = repo.path().join("hooks");
+        fs::create_dir_all(&hooks_dir)
+            .with_context(|| format!("Error creating directory {}", hooks_dir.display()))?;
+        let hook_path = hooks_dir.join("commit-msg");
+        fs::write(&hook_path, hook)
+            .with_context(|| format!("Error writing hook to {}", hook_path.display()))?;
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&hook_path, fs::Permissions::from_mode(0o755))
+                .with_context(|| format!("Error making {} executable", hook_path.display()))?;
+        }
+        Ok(())
+    }
+
     pub fn set_upstream(&self, root: &Path) -> anyhow::Result<()> {
         let upstream = match &self.upstream {
             Some(upstream) => upstream,
@@ -180,6 +326,14 @@ impl Repository {
     pub fn name(&self) -> &String {
         &self.path
     }
+    /// Apply the workspace's path case policy to the path of the repository
+    pub fn with_path_case(mut self, path_case: PathCase) -> Repository {
+        self.path = path_case.apply(&self.path);
+        self
+    }
+    pub fn url(&self) -> &String {
+        &self.url
+    }
     pub fn get_path(&self, root: &Path) -> anyhow::Result<PathBuf> {
         let joined = root.join(self.name());
         joined
@@ -76,6 +76,19 @@ pub fn ensure_workspace_dir_exists(path: &PathBuf) -> anyhow::Result<PathBuf> {
         .with_context(|| format!("Error canonicalizing workspace path {}", &path.display()))
 }
 
+/// The message of an error followed by those of its causes. ureq's errors repeat their cause
+/// in their own message, so a cause that the previous message ends with is left out.
+pub fn error_messages(error: &anyhow::Error) -> Vec<String> {
+    let mut messages: Vec<String> = vec![];
+    for cause in error.chain() {
+        let message = cause.to_string();
+        if !messages.last().is_some_and(|last| last.ends_with(&message)) {
+            messages.push(message);
+        }
+    }
+    messages
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -104,6 +117,19 @@ mod tests {
         assert_eq!(build_prompt_text("", ": ", true, Some("Y/n")), " [Y/n]: ");
     }
 
+    #[test]
+    fn test_error_messages() {
+        let cause = io::Error::new(io::ErrorKind::NotFound, "https://a isn't cached");
+        let error = anyhow::Error::new(ureq::Error::from(cause)).context("Error fetching");
+        assert_eq!(
+            error_messages(&error),
+            vec![
+                "Error fetching".to_string(),
+                "Network Error: https://a isn't cached".to_string()
+            ]
+        );
+    }
+
     #[test]
     fn test_expand_workspace_path() {
         let path = PathBuf::from("/test/path");
@@ -182,6 +182,17 @@ impl GiteaContainer {
         self.create_tmp_file(tmp_dir, "git/config", &file_content);
     }
 
+    /// Creates an organization with a random name, returning the name
+    pub fn add_organization(&self) -> String {
+        let org_name: String = rand::rng()
+            .sample_iter(Alphanumeric)
+            .take(8) // Adjust length as needed
+            .map(char::from)
+            .collect();
+        self.create_organization(&org_name);
+        org_name
+    }
+
     fn create_organization(&self, org: &str) {
         #[derive(Serialize)]
         struct CreateOrg {
@@ -221,12 +232,7 @@ impl GiteaContainer {
     pub fn setup(&self) -> (TempDir, String) {
         let tmp_dir = TempDir::new().unwrap();
 
-        let org_name: String = rand::rng()
-            .sample_iter(Alphanumeric)
-            .take(8) // Adjust length as needed
-            .map(char::from)
-            .collect();
-        self.create_organization(&org_name);
+        let org_name = self.add_organization();
 
         let config_content = WORKSPACE_TEMPLATE.replace("ORG", &org_name);
         self.create_tmp_file(&tmp_dir, "workspace.toml", &config_content);
@@ -304,6 +310,49 @@ impl GiteaContainer {
         }
     }
 
+    /// Forks a repository into another organization. Empty repositories can't be forked, so
+    /// the source repository needs at least one commit.
+    pub fn fork_repo(&self, org_name: &str, repo: &str, fork_org_name: &str, fork_name: &str) {
+        #[derive(Serialize)]
+        struct CreateFork {
+            organization: String,
+            name: String,
+        }
+
+        let url = format!("{}/api/v1/repos/{}/{}/forks", self.url, org_name, repo);
+        self.http_client
+            .post(&url)
+            .bearer_auth(&self.token)
+            .json(&CreateFork {
+                organization: fork_org_name.to_string(),
+                name: fork_name.to_string(),
+            })
+            .send()
+            .unwrap_or_else(|_| panic!("expect to fork repo {}", repo))
+            .error_for_status()
+            .unwrap_or_else(|_| panic!("expect 2xx http response for forking {} repo", repo));
+    }
+
+    /// Renames a repository within its organization
+    pub fn rename_repo(&self, org_name: &str, repo: &str, new_name: &str) {
+        #[derive(Serialize)]
+        struct EditRepo {
+            name: String,
+        }
+
+        let url = format!("{}/api/v1/repos/{}/{}", self.url, org_name, repo);
+        self.http_client
+            .patch(&url)
+            .bearer_auth(&self.token)
+            .json(&EditRepo {
+                name: new_name.to_string(),
+            })
+            .send()
+            .unwrap_or_else(|_| panic!("expect to rename repo {}", repo))
+            .error_for_status()
+            .unwrap_or_else(|_| panic!("expect 2xx http response for renaming {} repo", repo));
+    }
+
     /// Creates a new commit in the specified repository with the given file contents
     pub fn commit_to_repo(&self, org_name: &str, repo: &str, filepath: &str, body: &GiteaCommit) {
         let url = format!(
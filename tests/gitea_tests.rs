@@ -1,10 +1,11 @@
 mod container;
 
 use container::{GiteaCommit, GiteaContainer};
-use git_workspace::commands::{archive, execute_cmd, fetch, lock, update};
+use git_workspace::commands::{archive, execute_cmd, fetch, lock, update, LockOptions};
+use git_workspace::config::ProviderFilter;
 use rstest::*;
 use std::{
-    fs::{read_to_string, remove_dir_all},
+    fs::{read_to_string, remove_dir_all, write},
     path::Path,
     sync::OnceLock,
 };
@@ -31,8 +32,8 @@ fn cleanup() {
 }
 
 fn update_command(workspace_path: &Path) {
-    lock(workspace_path).unwrap();
-    update(workspace_path, 8).unwrap();
+    lock(workspace_path, &LockOptions::default()).unwrap();
+    update(workspace_path, 8, &ProviderFilter::default()).unwrap();
 }
 
 fn execute_command(workspace_path: &Path, cmd: &str, args_raw: &str) {
@@ -86,7 +87,7 @@ fn test_archive_command(gitea_container: &GiteaContainer) {
 
     // Test archive command
     gitea_container.delete_repos(&org_name, ["repo2"]);
-    archive(workspace, true).unwrap();
+    archive(workspace, true, &ProviderFilter::default()).unwrap();
 
     // Check if .git/config exists for repo2 is in the .archive directory
     let repo2 = workspace.join(format!(".archive/{}/repo2/.git/config", org_name));
@@ -125,3 +126,73 @@ fn test_fetch_and_run_commands(gitea_container: &GiteaContainer) {
     assert_eq!(branch.trim(), "ref: refs/heads/main");
     assert_eq!(readme, content);
 }
+
+#[rstest]
+fn test_update_sets_upstream_for_forks(gitea_container: &GiteaContainer) {
+    // Setup environment
+    let (tmp_dir, org_name) = gitea_container.setup();
+    let workspace = tmp_dir.path();
+
+    // Fork a repository from another organization into the workspace organization
+    let upstream_org_name = gitea_container.add_organization();
+    gitea_container.add_repos(&upstream_org_name, ["upstream"]);
+    let commit = GiteaCommit::new("main", "chore: initial commit", "Hello Orf");
+    gitea_container.commit_to_repo(&upstream_org_name, "upstream", "README.md", &commit);
+    gitea_container.fork_repo(&upstream_org_name, "upstream", &org_name, "fork");
+    update_command(workspace);
+
+    // Check that the fork has an upstream remote pointing at the parent
+    let config_path = workspace.join(format!("{}/fork/.git/config", org_name));
+    let config = read_to_string(&config_path).unwrap();
+    let upstream_url = format!("{}/{}/upstream.git", gitea_container.url, upstream_org_name);
+    assert!(
+        config.contains("[remote \"upstream\"]"),
+        "{} has no upstream remote",
+        config_path.display()
+    );
+    assert!(
+        config.contains(&upstream_url),
+        "{} does not contain {}",
+        config_path.display(),
+        upstream_url
+    );
+
+    gitea_container.reset(tmp_dir);
+}
+
+#[rstest]
+fn test_update_moves_renamed_repositories(gitea_container: &GiteaContainer) {
+    // Setup environment
+    let (tmp_dir, org_name) = gitea_container.setup();
+    let workspace = tmp_dir.path();
+    gitea_container.add_repos(&org_name, ["repo1"]);
+    update_command(workspace);
+
+    // Leave some local work in the checkout, then rename the repository on the server
+    let notes = format!("{}/repo1/notes.txt", org_name);
+    write(workspace.join(&notes), "Local work").unwrap();
+    gitea_container.rename_repo(&org_name, "repo1", "renamed");
+    update_command(workspace);
+
+    // Check that the checkout was moved rather than cloned again
+    let renamed_notes = workspace.join(format!("{}/renamed/notes.txt", org_name));
+    assert!(
+        renamed_notes.exists(),
+        "{} does not exist",
+        renamed_notes.display()
+    );
+    assert!(!workspace.join(format!("{}/repo1", org_name)).exists());
+    let config_path = workspace.join(format!("{}/renamed/.git/config", org_name));
+    let config = read_to_string(&config_path).unwrap();
+    assert!(
+        config.contains(&format!("{}/renamed.git", org_name)),
+        "{} does not point at the renamed repository",
+        config_path.display()
+    );
+
+    // Nothing is left to archive
+    archive(workspace, true, &ProviderFilter::default()).unwrap();
+    assert!(!workspace.join(".archive").join(&org_name).exists());
+
+    gitea_container.reset(tmp_dir);
+}